@@ -0,0 +1,697 @@
+use std::{
+    path::PathBuf,
+    sync::{mpsc::Receiver, Arc},
+};
+
+use console::{Key, Term};
+
+use crate::{
+    broadcast::Broadcast,
+    game::{format_duration, game_board, play, player_name, GameEnd, GameSummary, PlayResult},
+    input::{spawn_input_thread, UserInput},
+    maps::Map,
+    menu::{draw_panel, prompt_text, Menu},
+    render::{terminal_renderer, CellShape, Frame},
+    scores::{table_name, HighScores, ScoreEntry},
+    settings::{
+        BoardSize, Difficulty, GameSettings, KeyBindings, KeyName, KeyPreset, BOUND_INPUTS,
+    },
+    sim::{GameState, WallMode},
+};
+
+const MAX_NAME_LEN: usize = 16;
+
+fn input_name(input: UserInput) -> &'static str {
+    BOUND_INPUTS
+        .iter()
+        .find_map(|(i, name)| (*i == input).then_some(*name))
+        .unwrap_or("nothing")
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GameMode {
+    Classic,
+    /// Two players sharing the keyboard on the classic board
+    Versus,
+    Map(Arc<Map>),
+}
+
+impl GameMode {
+    pub fn name(&self) -> String {
+        match self {
+            GameMode::Classic => "Classic".to_string(),
+            GameMode::Versus => "Versus".to_string(),
+            GameMode::Map(map) => format!("Map: {}", map.name),
+        }
+    }
+
+    fn map(&self) -> Option<Arc<Map>> {
+        match self {
+            GameMode::Classic | GameMode::Versus => None,
+            GameMode::Map(map) => Some(map.clone()),
+        }
+    }
+
+    fn players(&self) -> usize {
+        match self {
+            GameMode::Versus => 2,
+            GameMode::Classic | GameMode::Map(_) => 1,
+        }
+    }
+}
+
+/// Everything shown outside of an actual game
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Screen {
+    Title,
+    ModeSelect,
+    Settings,
+    Controls,
+    HighScores,
+    InGame(GameMode),
+    GameOver {
+        mode: GameMode,
+        state: GameState,
+        summary: GameSummary,
+        /// The board as the game ended, shown under the game over menu
+        last_frame: Frame,
+    },
+    Quit,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TitleItem {
+    Play,
+    Settings,
+    Controls,
+    HighScores,
+    Quit,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SettingsItem {
+    Difficulty,
+    Speed,
+    Board,
+    Walls,
+    Cells,
+    Save,
+    Back,
+}
+
+impl SettingsItem {
+    /// The config file field the item changes, if any
+    fn field(self) -> Option<&'static str> {
+        match self {
+            SettingsItem::Difficulty => Some("difficulty"),
+            SettingsItem::Speed => Some("tick_ms"),
+            SettingsItem::Board => Some("board"),
+            SettingsItem::Walls => Some("wall_mode"),
+            SettingsItem::Cells => Some("cell_shape"),
+            SettingsItem::Save | SettingsItem::Back => None,
+        }
+    }
+}
+
+/// Tick lengths offered on the settings screen, `None` leaving it to the
+/// difficulty
+const TICK_CHOICES: [Option<f64>; 7] = [
+    None,
+    Some(150.0),
+    Some(100.0),
+    Some(80.0),
+    Some(62.5),
+    Some(40.0),
+    Some(25.0),
+];
+
+/// Board sizes offered on the settings screen, `None` filling the terminal
+const BOARD_CHOICES: [Option<BoardSize>; 5] = [
+    None,
+    Some(BoardSize {
+        width: 20,
+        height: 10,
+    }),
+    Some(BoardSize {
+        width: 30,
+        height: 15,
+    }),
+    Some(BoardSize {
+        width: 40,
+        height: 20,
+    }),
+    Some(BoardSize {
+        width: 60,
+        height: 30,
+    }),
+];
+
+/// The choice after `current`, going back to the first after the last or if
+/// `current` isn't one of them
+fn next_choice<T: Copy + PartialEq>(choices: &[T], current: T) -> T {
+    let idx = choices.iter().position(|choice| *choice == current);
+    choices[idx.map_or(0, |idx| (idx + 1) % choices.len())]
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ControlsItem {
+    Bind(UserInput),
+    Preset,
+    Save,
+    Back,
+}
+
+/// What to do with one input's keys on the controls screen
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RebindItem {
+    AddKey,
+    Clear,
+    Cancel,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum GameOverItem {
+    PlayAgain,
+    MainMenu,
+    Quit,
+}
+
+/// The top level of the program, moving between the menus and games
+pub struct App {
+    term: Term,
+    input_rcv: Receiver<Key>,
+    settings: GameSettings,
+    // where changes made in the menus are saved
+    config_path: Option<PathBuf>,
+    seed: Option<u64>,
+    record: Option<PathBuf>,
+    // shows the games to anyone watching
+    broadcast: Option<Broadcast>,
+    maps: Vec<Arc<Map>>,
+    scores: HighScores,
+    scores_path: Option<PathBuf>,
+    // table of the most recent game, shown first on the high score screen
+    last_table: Option<String>,
+    last_game: Option<GameSummary>,
+    player_name: String,
+}
+
+impl App {
+    /// Creates the app, using `seed` for every game if given and saving a
+    /// replay of the most recent game to `record`. `maps` are offered
+    /// alongside the built-in ones, rebound keys are saved to `config_path`,
+    /// and every game is shown live through `broadcast`.
+    pub fn new(
+        term: Term,
+        settings: GameSettings,
+        config_path: Option<PathBuf>,
+        seed: Option<u64>,
+        record: Option<PathBuf>,
+        maps: Vec<Map>,
+        broadcast: Option<Broadcast>,
+    ) -> anyhow::Result<Self> {
+        let scores_path = HighScores::default_path();
+        let scores = match &scores_path {
+            Some(path) => HighScores::load(path)?,
+            None => HighScores::default(),
+        };
+        let player_name = std::env::var("USER").unwrap_or_else(|_| "Player".to_string());
+        let input_rcv = spawn_input_thread(term.clone());
+
+        Ok(App {
+            term,
+            input_rcv,
+            settings,
+            config_path,
+            seed,
+            record,
+            broadcast,
+            maps: Map::builtins()
+                .into_iter()
+                .chain(maps)
+                .map(Arc::new)
+                .collect(),
+            scores,
+            scores_path,
+            last_table: None,
+            last_game: None,
+            player_name,
+        })
+    }
+
+    pub fn run(&mut self) -> anyhow::Result<()> {
+        let mut screen = Screen::Title;
+        while screen != Screen::Quit {
+            self.term.clear_screen()?;
+            screen = match screen {
+                Screen::Title => self.title()?,
+                Screen::ModeSelect => self.mode_select()?,
+                Screen::Settings => self.settings()?,
+                Screen::Controls => self.controls()?,
+                Screen::HighScores => self.high_scores()?,
+                Screen::InGame(mode) => self.in_game(mode)?,
+                Screen::GameOver {
+                    mode,
+                    state,
+                    summary,
+                    last_frame,
+                } => self.game_over(mode, state, summary, &last_frame)?,
+                Screen::Quit => Screen::Quit,
+            };
+        }
+        self.term.clear_screen()?;
+
+        Ok(())
+    }
+
+    /// How the most recently played game went, if there was one
+    pub fn last_game(&self) -> Option<GameSummary> {
+        self.last_game.clone()
+    }
+
+    fn title(&mut self) -> anyhow::Result<Screen> {
+        let mut menu = Menu::new(
+            "S N A K E",
+            vec![
+                ("Play".to_string(), TitleItem::Play),
+                ("Settings".to_string(), TitleItem::Settings),
+                ("Controls".to_string(), TitleItem::Controls),
+                ("High Scores".to_string(), TitleItem::HighScores),
+                ("Quit".to_string(), TitleItem::Quit),
+            ],
+        );
+        Ok(match menu.run(&mut self.term, &self.input_rcv)? {
+            Some(TitleItem::Play) => Screen::ModeSelect,
+            Some(TitleItem::Settings) => Screen::Settings,
+            Some(TitleItem::Controls) => Screen::Controls,
+            Some(TitleItem::HighScores) => Screen::HighScores,
+            Some(TitleItem::Quit) | None => Screen::Quit,
+        })
+    }
+
+    fn mode_select(&mut self) -> anyhow::Result<Screen> {
+        let modes: Vec<GameMode> = [GameMode::Classic, GameMode::Versus]
+            .into_iter()
+            .chain(self.maps.iter().cloned().map(GameMode::Map))
+            .collect();
+        let mut menu = Menu::new(
+            "Select Mode",
+            modes.iter().map(GameMode::name).zip(0..).collect(),
+        );
+        Ok(match menu.run(&mut self.term, &self.input_rcv)? {
+            Some(idx) => Screen::InGame(modes[idx].clone()),
+            None => Screen::Title,
+        })
+    }
+
+    /// Lets the player change how games are played and save it to the
+    /// config file
+    fn settings(&mut self) -> anyhow::Result<Screen> {
+        let seed = match self.seed {
+            Some(seed) => seed.to_string(),
+            None => "random".to_string(),
+        };
+        let mut details = vec![format!("Seed: {seed}")];
+        if let Some(broadcast) = &self.broadcast {
+            details.push(format!("Broadcasting on {}", broadcast.local_addr()));
+        }
+        if let Some(path) = &self.config_path {
+            details.push(format!("Config: {}", path.display()));
+        }
+
+        let mut settings = self.settings.clone();
+        // only what's changed here is saved, so flags given on the command
+        // line don't end up in the config file
+        let mut edited = Vec::new();
+        let mut selected = 0;
+        loop {
+            let speed = match settings.tick_ms {
+                Some(tick_ms) => format!("{tick_ms}ms ticks"),
+                None => "set by difficulty".to_string(),
+            };
+            let board = match settings.board {
+                Some(size) => format!("{}x{}", size.width, size.height),
+                None => "fit terminal".to_string(),
+            };
+            let items = vec![
+                (
+                    format!("Difficulty: {}", settings.difficulty),
+                    SettingsItem::Difficulty,
+                ),
+                (format!("Speed: {speed}"), SettingsItem::Speed),
+                (format!("Board: {board}"), SettingsItem::Board),
+                (
+                    format!("Walls: {}", settings.wall_mode),
+                    SettingsItem::Walls,
+                ),
+                (
+                    format!("Cells: {}", settings.cell_shape),
+                    SettingsItem::Cells,
+                ),
+                ("Save".to_string(), SettingsItem::Save),
+                ("Back".to_string(), SettingsItem::Back),
+            ];
+            let mut menu = Menu::new("Settings", items)
+                .with_details(details.clone())
+                .with_selected(selected);
+            self.term.clear_screen()?;
+            let choice = menu.run(&mut self.term, &self.input_rcv)?;
+            selected = menu.selected();
+            if let Some(field) = choice.and_then(SettingsItem::field) {
+                if !edited.contains(&field) {
+                    edited.push(field);
+                }
+            }
+            match choice {
+                Some(SettingsItem::Difficulty) => {
+                    settings.difficulty = next_choice(&Difficulty::ALL, settings.difficulty);
+                }
+                Some(SettingsItem::Speed) => {
+                    settings.tick_ms = next_choice(&TICK_CHOICES, settings.tick_ms);
+                }
+                Some(SettingsItem::Board) => {
+                    settings.board = next_choice(&BOARD_CHOICES, settings.board);
+                }
+                Some(SettingsItem::Walls) => {
+                    settings.wall_mode = next_choice(&WallMode::ALL, settings.wall_mode);
+                }
+                Some(SettingsItem::Cells) => {
+                    settings.cell_shape = next_choice(&CellShape::ALL, settings.cell_shape);
+                }
+                Some(SettingsItem::Save) => {
+                    let saved = settings.validate().and_then(|()| match &self.config_path {
+                        Some(path) => settings.save_rules(path, &edited),
+                        None => Ok(()),
+                    });
+                    match saved {
+                        Ok(()) => {
+                            self.settings = settings;
+                            return Ok(Screen::Title);
+                        }
+                        Err(e) => self.show_message(&format!("Couldn't save: {e:#}"))?,
+                    }
+                }
+                Some(SettingsItem::Back) | None => return Ok(Screen::Title),
+            }
+        }
+    }
+
+    /// Lets the player rebind the in game keys and save them to the config file
+    fn controls(&mut self) -> anyhow::Result<Screen> {
+        let mut keys = self.settings.keys.clone();
+        let mut selected = 0;
+        loop {
+            let mut items: Vec<(String, ControlsItem)> = BOUND_INPUTS
+                .iter()
+                .map(|(input, name)| {
+                    let bound: Vec<String> =
+                        keys.keys(*input).iter().map(ToString::to_string).collect();
+                    (
+                        format!("{name}: {}", bound.join(", ")),
+                        ControlsItem::Bind(*input),
+                    )
+                })
+                .collect();
+            items.push((format!("Preset: {}", keys.preset), ControlsItem::Preset));
+            items.push(("Save".to_string(), ControlsItem::Save));
+            items.push(("Back".to_string(), ControlsItem::Back));
+            let mut menu = Menu::new("Controls", items).with_selected(selected);
+            self.term.clear_screen()?;
+            let choice = menu.run(&mut self.term, &self.input_rcv)?;
+            selected = menu.selected();
+            match choice {
+                Some(ControlsItem::Bind(input)) => self.rebind(&mut keys, input)?,
+                Some(ControlsItem::Preset) => {
+                    keys = KeyBindings::from_preset(next_choice(&KeyPreset::ALL, keys.preset));
+                }
+                Some(ControlsItem::Save) => {
+                    let saved = keys.validate().and_then(|()| match &self.config_path {
+                        Some(path) => GameSettings::save_keys(path, &keys),
+                        None => Ok(()),
+                    });
+                    match saved {
+                        Ok(()) => {
+                            self.settings.keys = keys;
+                            return Ok(Screen::Title);
+                        }
+                        Err(e) => self.show_message(&format!("Couldn't save: {e:#}"))?,
+                    }
+                }
+                Some(ControlsItem::Back) | None => return Ok(Screen::Title),
+            }
+        }
+    }
+
+    /// Lets the player add a key to `input`'s bindings or clear them. Clearing
+    /// and cancelling are menu entries rather than keys, so that any key,
+    /// Escape included, can be bound.
+    fn rebind(&mut self, keys: &mut KeyBindings, input: UserInput) -> anyhow::Result<()> {
+        let items = vec![
+            ("Add a key".to_string(), RebindItem::AddKey),
+            ("Clear".to_string(), RebindItem::Clear),
+            ("Cancel".to_string(), RebindItem::Cancel),
+        ];
+        let mut menu = Menu::new(input_name(input), items);
+        self.term.clear_screen()?;
+        match menu.run(&mut self.term, &self.input_rcv)? {
+            Some(RebindItem::AddKey) => {}
+            Some(RebindItem::Clear) => {
+                if let Some(bound) = keys.keys_mut(input) {
+                    bound.clear();
+                }
+                return Ok(());
+            }
+            Some(RebindItem::Cancel) | None => return Ok(()),
+        }
+
+        let lines = [format!("Press a key for {}", input_name(input))];
+        self.term.clear_screen()?;
+        draw_panel(&mut self.term, &lines, None)?;
+        let key = self.input_rcv.recv()?;
+        let Some(name) = KeyName::new(key) else {
+            return self.show_message("That key can't be bound");
+        };
+        match keys.input_for(name.key()) {
+            UserInput::Unknown => {
+                if let Some(bound) = keys.keys_mut(input) {
+                    bound.push(name);
+                }
+            }
+            other if other == input => {}
+            other => {
+                let msg = format!("{name} is already bound to {}", input_name(other));
+                self.show_message(&msg)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Shows `msg` in a panel until a key is pressed
+    fn show_message(&mut self, msg: &str) -> anyhow::Result<()> {
+        self.term.clear_screen()?;
+        let lines = [msg.to_string(), String::new(), "Press any key".to_string()];
+        draw_panel(&mut self.term, &lines, None)?;
+        self.input_rcv.recv()?;
+
+        Ok(())
+    }
+
+    fn high_scores(&mut self) -> anyhow::Result<Screen> {
+        let names: Vec<String> = self.scores.table_names().cloned().collect();
+        if names.is_empty() {
+            let lines = [
+                "High Scores".to_string(),
+                String::new(),
+                "No high scores yet".to_string(),
+                String::new(),
+                "Press any key".to_string(),
+            ];
+            draw_panel(&mut self.term, &lines, None)?;
+            self.input_rcv.recv()?;
+            return Ok(Screen::Title);
+        }
+
+        let mut idx = self
+            .last_table
+            .as_ref()
+            .and_then(|last| names.iter().position(|n| n == last))
+            .unwrap_or(0);
+        loop {
+            let name = &names[idx];
+            let mut lines = vec![
+                "High Scores".to_string(),
+                format!("< {name} >"),
+                String::new(),
+            ];
+            for (i, entry) in self.scores.table(name).iter().enumerate() {
+                lines.push(format!(
+                    "{:>2}. {:<width$} {:>6}",
+                    i + 1,
+                    entry.name,
+                    entry.score,
+                    width = MAX_NAME_LEN
+                ));
+            }
+            lines.push(String::new());
+            lines.push("Left/Right: switch table".to_string());
+            self.term.clear_screen()?;
+            draw_panel(&mut self.term, &lines, None)?;
+            match self.input_rcv.recv()? {
+                Key::ArrowLeft => idx = idx.checked_sub(1).unwrap_or(names.len() - 1),
+                Key::ArrowRight => idx = (idx + 1) % names.len(),
+                _ => return Ok(Screen::Title),
+            }
+        }
+    }
+
+    /// Saves `score` if it makes the table for this game, asking the player
+    /// for their name first
+    fn record_score(&mut self, table: String, score: usize) -> anyhow::Result<()> {
+        if self.scores.qualifies(&table, score) {
+            self.term.clear_screen()?;
+            let title = format!("New high score: {score}!  Enter your name");
+            if let Some(name) = prompt_text(
+                &mut self.term,
+                &self.input_rcv,
+                &title,
+                &self.player_name,
+                MAX_NAME_LEN,
+            )? {
+                self.player_name.clone_from(&name);
+                let entry = ScoreEntry::new(name, score);
+                if let Err(e) = self
+                    .scores
+                    .record(self.scores_path.as_deref(), &table, entry)
+                {
+                    // a score that can't be saved shouldn't end the session
+                    self.term.clear_screen()?;
+                    let lines = [
+                        format!("Couldn't save score: {e:#}"),
+                        "Press any key".to_string(),
+                    ];
+                    draw_panel(&mut self.term, &lines, None)?;
+                    self.input_rcv.recv()?;
+                }
+            }
+        }
+        self.last_table = Some(table);
+
+        Ok(())
+    }
+
+    fn in_game(&mut self, mode: GameMode) -> anyhow::Result<Screen> {
+        if let Err(e) = game_board(&self.term, &self.settings, mode.map().as_deref()) {
+            let lines = vec![
+                "Can't start".to_string(),
+                String::new(),
+                e.to_string(),
+                "Enlarge the terminal or pick a smaller board".to_string(),
+                String::new(),
+                "Press any key".to_string(),
+            ];
+            draw_panel(&mut self.term, &lines, None)?;
+            self.input_rcv.recv()?;
+            return Ok(Screen::ModeSelect);
+        }
+        let seed = self.seed.unwrap_or_else(rand::random);
+        let PlayResult {
+            end,
+            summary,
+            replay,
+            last_frame,
+        } = play(
+            self.term.clone(),
+            &self.input_rcv,
+            &self.settings,
+            mode.map(),
+            seed,
+            mode.players(),
+            self.broadcast.as_ref(),
+        )?;
+        if let Some(path) = &self.record {
+            replay.save(path)?;
+        }
+        // drop keys still queued from the game so they don't leak into the menus
+        while self.input_rcv.try_recv().is_ok() {}
+
+        self.last_game = Some(summary.clone());
+
+        Ok(match end {
+            GameEnd::Finished(state) => {
+                // high scores are only kept for playing alone
+                if let [player] = summary.players.as_slice() {
+                    let table = table_name(&mode.name(), &replay.config);
+                    self.record_score(table, player.score)?;
+                }
+                Screen::GameOver {
+                    mode,
+                    state,
+                    summary,
+                    last_frame,
+                }
+            }
+            GameEnd::Quit => Screen::Title,
+        })
+    }
+
+    /// Shows how the game went over its final board, offering to play the
+    /// same mode again
+    fn game_over(
+        &mut self,
+        mode: GameMode,
+        state: GameState,
+        summary: GameSummary,
+        last_frame: &Frame,
+    ) -> anyhow::Result<Screen> {
+        terminal_renderer(self.term.clone(), self.settings.plain).draw(last_frame)?;
+        let title = match state {
+            GameState::Win => "You Win!".to_string(),
+            GameState::RoundOver {
+                winner: Some(winner),
+            } => format!("{} Wins!", player_name(winner)),
+            GameState::RoundOver { winner: None } => "Draw".to_string(),
+            _ => "Game Over".to_string(),
+        };
+        let mut details = Vec::new();
+        match summary.players.as_slice() {
+            [player] => {
+                details.push(format!("Score: {}", player.score));
+                details.push(format!("Apples: {}", player.apples));
+                details.push(format!("Length: {}", player.length));
+                details.push(format!("Time: {}", format_duration(summary.duration)));
+                if state == GameState::Win {
+                    details.push("Filled the board!".to_string());
+                }
+                if let Some(death) = player.death {
+                    details.push(death.to_string());
+                }
+            }
+            players => {
+                for (i, player) in players.iter().enumerate() {
+                    details.push(format!("{}: {}", player_name(i), player.score));
+                }
+                details.push(format!("Time: {}", format_duration(summary.duration)));
+                for (i, player) in players.iter().enumerate() {
+                    if let Some(death) = player.death {
+                        details.push(format!("{}: {death}", player_name(i)));
+                    }
+                }
+            }
+        }
+        let mut menu = Menu::new(
+            title,
+            vec![
+                ("Play Again".to_string(), GameOverItem::PlayAgain),
+                ("Main Menu".to_string(), GameOverItem::MainMenu),
+                ("Quit".to_string(), GameOverItem::Quit),
+            ],
+        )
+        .with_details(details);
+        Ok(match menu.run(&mut self.term, &self.input_rcv)? {
+            Some(GameOverItem::PlayAgain) => Screen::InGame(mode),
+            Some(GameOverItem::MainMenu) | None => Screen::Title,
+            Some(GameOverItem::Quit) => Screen::Quit,
+        })
+    }
+}
@@ -0,0 +1,83 @@
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Context};
+use rusty_snake::{
+    net::DEFAULT_ADDR,
+    server::Server,
+    settings::{flag_value, GameSettings, RuleOverrides},
+};
+
+struct Args {
+    bind: String,
+    min_players: usize,
+    max_players: usize,
+    seed: Option<u64>,
+    config: Option<PathBuf>,
+    rules: RuleOverrides,
+}
+
+fn parse_args() -> anyhow::Result<Args> {
+    let mut args = Args {
+        bind: DEFAULT_ADDR.to_string(),
+        min_players: 2,
+        max_players: 4,
+        seed: None,
+        config: None,
+        rules: RuleOverrides::default(),
+    };
+    let mut argv = std::env::args().skip(1);
+    while let Some(arg) = argv.next() {
+        match arg.as_str() {
+            "--bind" => args.bind = flag_value(&mut argv, &arg)?,
+            "--min-players" => {
+                let val = flag_value(&mut argv, &arg)?;
+                args.min_players = val
+                    .parse()
+                    .context("--min-players must be an unsigned integer")?;
+            }
+            "--max-players" => {
+                let val = flag_value(&mut argv, &arg)?;
+                args.max_players = val
+                    .parse()
+                    .context("--max-players must be an unsigned integer")?;
+            }
+            "--seed" => {
+                let val = flag_value(&mut argv, &arg)?;
+                args.seed = Some(val.parse().context("--seed must be an unsigned integer")?);
+            }
+            "--config" => args.config = Some(flag_value(&mut argv, &arg)?.into()),
+            _ if args.rules.parse_flag(&arg, &mut argv)? => {}
+            _ => return Err(anyhow!("Unrecognized argument: {arg}")),
+        }
+    }
+
+    Ok(args)
+}
+
+/// Loads the game settings from the config file given, if any, then applies
+/// the overrides from the command line. Unlike the game, the server doesn't
+/// read the player's own config file.
+fn load_settings(args: &Args) -> anyhow::Result<GameSettings> {
+    let mut settings = match &args.config {
+        Some(path) => GameSettings::load(path)?,
+        None => GameSettings::default(),
+    };
+    args.rules.apply(&mut settings);
+    settings.validate()?;
+
+    Ok(settings)
+}
+
+fn main() -> anyhow::Result<()> {
+    let args = parse_args()?;
+    let settings = load_settings(&args)?;
+    let server = Server::bind(
+        &args.bind,
+        settings,
+        args.seed,
+        args.min_players..=args.max_players,
+    )?;
+    eprintln!("Listening on {}", server.local_addr()?);
+
+    server.run()
+}
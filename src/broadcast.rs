@@ -0,0 +1,195 @@
+use std::{
+    io::BufReader,
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    sync::{Arc, Mutex},
+    thread,
+};
+
+use anyhow::Context;
+
+use crate::{
+    net::{send, ClientMsg, Outbox, ServerMsg, HANDSHAKE_TIMEOUT, PROTOCOL_VERSION, WRITE_TIMEOUT},
+    sim::{GameState, SimConfig, Simulation, Snapshot},
+};
+
+/// Everyone watching, and the game as they last saw it
+struct Audience {
+    watchers: Vec<Outbox>,
+    game: Option<(SimConfig, Snapshot)>,
+}
+
+impl Audience {
+    /// Queues a message for every watcher, dropping any that can't keep up.
+    /// Each has its own outbox, so a slow one never holds up the game.
+    fn send(&mut self, msg: &ServerMsg) {
+        self.watchers.retain(|outbox| outbox.send(msg));
+    }
+
+    /// Catches a new watcher up on the game being played, if there is one
+    fn welcome(&self, outbox: &Outbox) -> bool {
+        let msg = match &self.game {
+            Some((config, snapshot)) => ServerMsg::Start {
+                you: None,
+                config: config.clone(),
+                snapshot: snapshot.clone(),
+            },
+            None => ServerMsg::Lobby {
+                players: Vec::new(),
+                in_round: false,
+            },
+        };
+        outbox.send(&msg)
+    }
+}
+
+/// Lets spectators watch a local game over TCP, speaking the same protocol
+/// as the server. Watchers can connect at any time: they're sent the game as
+/// it stands, then what changes each tick.
+pub struct Broadcast {
+    addr: SocketAddr,
+    audience: Arc<Mutex<Audience>>,
+}
+
+impl Broadcast {
+    /// Starts accepting watchers on `addr` in the background
+    pub fn bind(addr: impl ToSocketAddrs) -> anyhow::Result<Self> {
+        let listener = TcpListener::bind(addr).context("Failed to start broadcasting")?;
+        let audience = Arc::new(Mutex::new(Audience {
+            watchers: Vec::new(),
+            game: None,
+        }));
+        let broadcast = Broadcast {
+            addr: listener.local_addr()?,
+            audience: audience.clone(),
+        };
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let audience = audience.clone();
+                thread::spawn(move || greet(stream, &audience));
+            }
+        });
+
+        Ok(broadcast)
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Shows watchers a new game, or the same one started over
+    pub fn start(&self, sim: &Simulation) {
+        let mut audience = self.audience.lock().unwrap();
+        let config = sim.config().clone();
+        let snapshot = sim.snapshot();
+        audience.send(&ServerMsg::Start {
+            you: None,
+            config: config.clone(),
+            snapshot: snapshot.clone(),
+        });
+        audience.game = Some((config, snapshot));
+    }
+
+    /// Sends watchers whatever changed since they last heard
+    pub fn update(&self, sim: &Simulation) {
+        let mut audience = self.audience.lock().unwrap();
+        let Some((_, seen)) = &mut audience.game else {
+            return;
+        };
+        let next = sim.snapshot();
+        let delta = seen.delta(&next);
+        *seen = next;
+        audience.send(&ServerMsg::Tick(delta));
+    }
+
+    /// Tells watchers how the game ended
+    pub fn finish(&self, state: GameState) {
+        let mut audience = self.audience.lock().unwrap();
+        audience.game = None;
+        audience.send(&ServerMsg::Over(state));
+    }
+}
+
+/// Waits for someone who's connected to ask to watch, then adds them to the
+/// audience. Anyone trying to play is turned away.
+fn greet(mut stream: TcpStream, audience: &Mutex<Audience>) {
+    let _ = stream.set_nodelay(true);
+    let _ = stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT));
+    let _ = stream.set_write_timeout(Some(WRITE_TIMEOUT));
+    let Ok(reader) = stream.try_clone() else {
+        return;
+    };
+    let error = match ClientMsg::read(&mut BufReader::new(reader)) {
+        Ok(Some(ClientMsg::Watch { version })) if version == PROTOCOL_VERSION => {
+            let Ok(outbox) = Outbox::new(stream) else {
+                return;
+            };
+            let mut audience = audience.lock().unwrap();
+            if audience.welcome(&outbox) {
+                audience.watchers.push(outbox);
+            }
+            return;
+        }
+        Ok(Some(ClientMsg::Watch { version })) => {
+            format!("The game speaks version {PROTOCOL_VERSION}, not {version}")
+        }
+        Ok(Some(_)) => "This game can only be watched".to_string(),
+        Ok(None) | Err(_) => return,
+    };
+    let _ = send(&mut stream, &ServerMsg::Error(error));
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::{
+        input::UserInput,
+        sim::{Board, WallMode},
+    };
+
+    use super::*;
+
+    #[test]
+    fn watchers_joining_mid_game_catch_up_then_stay_in_step() {
+        let mut sim = Simulation::new(SimConfig {
+            seed: 7,
+            ..SimConfig::new(Board::new(20, 10, WallMode::Solid), 1)
+        });
+        let broadcast = Broadcast::bind("127.0.0.1:0").unwrap();
+        broadcast.start(&sim);
+        sim.update_state(UserInput::Down);
+        broadcast.update(&sim);
+
+        let mut stream = TcpStream::connect(broadcast.local_addr()).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let watch = ClientMsg::Watch {
+            version: PROTOCOL_VERSION,
+        };
+        send(&mut stream, &watch).unwrap();
+        let Some(ServerMsg::Start {
+            you: None,
+            mut snapshot,
+            ..
+        }) = ServerMsg::read(&mut reader).unwrap()
+        else {
+            panic!("Expected the game so far");
+        };
+        assert_eq!(snapshot, sim.snapshot());
+
+        for input in [UserInput::Right, UserInput::Right, UserInput::Up] {
+            sim.update_state(input);
+            broadcast.update(&sim);
+            let Some(ServerMsg::Tick(delta)) = ServerMsg::read(&mut reader).unwrap() else {
+                panic!("Expected a tick");
+            };
+            snapshot.apply(&delta);
+            assert_eq!(snapshot, sim.snapshot());
+        }
+        broadcast.finish(GameState::Continue);
+        let over = ServerMsg::read(&mut reader).unwrap();
+        assert_eq!(over, Some(ServerMsg::Over(GameState::Continue)));
+    }
+}
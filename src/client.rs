@@ -0,0 +1,255 @@
+use std::{
+    io::BufReader,
+    net::TcpStream,
+    sync::mpsc::{channel, Receiver, RecvTimeoutError},
+    thread,
+    time::Duration,
+};
+
+use anyhow::{bail, Context};
+use console::{Key, Term};
+
+use crate::{
+    game::{player_name, wait_for_room},
+    input::{spawn_input_thread, UserInput},
+    menu::draw_panel,
+    net::{send, ClientMsg, LobbyEntry, ServerMsg, PROTOCOL_VERSION},
+    render::{compose_snapshot, terminal_renderer, Renderer},
+    settings::GameSettings,
+    sim::{GameState, SimConfig, Snapshot},
+};
+
+/// How long to wait for the server between checks for key presses
+const POLL: Duration = Duration::from_millis(10);
+
+/// A round being played, as the server last described it
+struct OnlineRound {
+    /// The player this client controls, or `None` when watching
+    you: Option<usize>,
+    config: SimConfig,
+    snapshot: Snapshot,
+    renderer: Box<dyn Renderer>,
+    term_size: (u16, u16),
+}
+
+/// Reads messages from the server on a background thread. The returned
+/// channel closes when the connection does.
+fn spawn_server_reader(stream: TcpStream) -> Receiver<anyhow::Result<ServerMsg>> {
+    let (tx, rx) = channel();
+    thread::spawn(move || {
+        let mut reader = BufReader::new(stream);
+        loop {
+            let msg = match ServerMsg::read(&mut reader) {
+                Ok(Some(msg)) => Ok(msg),
+                Ok(None) => break,
+                Err(e) => Err(e),
+            };
+            let failed = msg.is_err();
+            if tx.send(msg).is_err() || failed {
+                break;
+            }
+        }
+    });
+
+    rx
+}
+
+/// How a finished round went, from the point of view of player `you`, or of
+/// a spectator if there isn't one
+fn describe_outcome(state: GameState, you: Option<usize>) -> String {
+    match state {
+        GameState::Continue => "Stopped early".to_string(),
+        GameState::Over(death) => format!("Game Over: {death}"),
+        GameState::Win if you.is_some() => "You filled the board!".to_string(),
+        GameState::Win => "The board was filled".to_string(),
+        GameState::RoundOver {
+            winner: Some(winner),
+        } if Some(winner) == you => "You win!".to_string(),
+        GameState::RoundOver {
+            winner: Some(winner),
+        } => format!("{} wins", player_name(winner)),
+        GameState::RoundOver { winner: None } => "Draw".to_string(),
+    }
+}
+
+/// Joins the server at `addr` as `name`, waiting in its lobby and playing
+/// rounds until the player leaves with Escape
+pub fn play_online(
+    term: Term,
+    addr: &str,
+    name: &str,
+    settings: &GameSettings,
+) -> anyhow::Result<()> {
+    let hello = ClientMsg::Hello {
+        version: PROTOCOL_VERSION,
+        name: name.to_string(),
+    };
+    run(term, addr, settings, hello)
+}
+
+/// Watches the game at `addr` without taking part, until the viewer leaves
+/// with Escape. This works against both a server and a local game that's
+/// being broadcast.
+pub fn watch(term: Term, addr: &str, settings: &GameSettings) -> anyhow::Result<()> {
+    let hello = ClientMsg::Watch {
+        version: PROTOCOL_VERSION,
+    };
+    run(term, addr, settings, hello)
+}
+
+/// Connects to `addr`, introduces itself with `hello`, then shows whatever
+/// the other end sends until Escape is pressed
+fn run(
+    mut term: Term,
+    addr: &str,
+    settings: &GameSettings,
+    hello: ClientMsg,
+) -> anyhow::Result<()> {
+    let watching = matches!(hello, ClientMsg::Watch { .. });
+    let mut stream =
+        TcpStream::connect(addr).with_context(|| format!("Couldn't connect to {addr}"))?;
+    stream.set_nodelay(true)?;
+    let server_rcv = spawn_server_reader(stream.try_clone()?);
+    let input_rcv = spawn_input_thread(term.clone());
+    send(&mut stream, &hello)?;
+
+    // the keys that leave while waiting for the terminal to fit a round
+    let quits = |key: &Key| *key == Key::Escape || settings.keys.input_for(key) == UserInput::Pause;
+    let mut lobby: Vec<LobbyEntry> = Vec::new();
+    let mut in_round = false;
+    let mut ready = false;
+    let mut last_result = None;
+    let mut round: Option<OnlineRound> = None;
+    let mut redraw = true;
+    loop {
+        while let Ok(key) = input_rcv.try_recv() {
+            match (&round, key) {
+                (_, Key::Escape) if watching => return Ok(()),
+                _ if watching => {}
+                (None, Key::Enter) => {
+                    ready = !ready;
+                    send(&mut stream, &ClientMsg::Ready(ready))?;
+                }
+                (None, Key::Escape) => return Ok(()),
+                (Some(_), key) => match settings.keys.input_for(&key) {
+                    UserInput::Pause => return Ok(()),
+                    input if input.dir().is_some() => {
+                        send(&mut stream, &ClientMsg::Input(input))?;
+                    }
+                    _ => {}
+                },
+                _ => {}
+            }
+        }
+
+        match server_rcv.recv_timeout(POLL) {
+            Ok(msg) => match msg? {
+                ServerMsg::Lobby {
+                    players,
+                    in_round: playing,
+                } => {
+                    lobby = players;
+                    in_round = playing;
+                    redraw = true;
+                }
+                ServerMsg::Start {
+                    you,
+                    config,
+                    snapshot,
+                } => {
+                    ready = false;
+                    term.clear_screen()?;
+                    if !wait_for_room(&term, &input_rcv, config.board, settings.cell_shape, quits)?
+                    {
+                        return Ok(());
+                    }
+                    round = Some(OnlineRound {
+                        you,
+                        config,
+                        snapshot,
+                        renderer: terminal_renderer(term.clone(), settings.plain),
+                        term_size: term.size(),
+                    });
+                    redraw = true;
+                }
+                ServerMsg::Tick(delta) => {
+                    if let Some(round) = &mut round {
+                        round.snapshot.apply(&delta);
+                        redraw = true;
+                    }
+                }
+                ServerMsg::Over(state) => {
+                    if let Some(round) = round.take() {
+                        last_result = Some(describe_outcome(state, round.you));
+                    }
+                    redraw = true;
+                }
+                ServerMsg::Error(msg) => bail!("The server said: {msg}"),
+            },
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => bail!("Lost the connection to the server"),
+        }
+
+        if let Some(round) = &mut round {
+            if term.size() != round.term_size {
+                if !wait_for_room(
+                    &term,
+                    &input_rcv,
+                    round.config.board,
+                    settings.cell_shape,
+                    quits,
+                )? {
+                    return Ok(());
+                }
+                round.term_size = term.size();
+                round.renderer.invalidate();
+                redraw = true;
+            }
+        }
+        if !redraw {
+            continue;
+        }
+        redraw = false;
+        match &mut round {
+            Some(round) => {
+                let mut frame = compose_snapshot(
+                    &round.config,
+                    &round.snapshot,
+                    &settings.colors,
+                    settings.cell_shape,
+                );
+                // which snake is theirs, over the top border
+                let label = match round.you {
+                    Some(you) => format!(" You: {} ", player_name(you)),
+                    None => " Watching ".to_string(),
+                };
+                frame.put_str(0, 0, &label, settings.colors.score_bar);
+                round.renderer.draw(&frame)?;
+            }
+            None => {
+                let title = if watching { "Watching" } else { "Lobby on" };
+                let mut lines = vec![format!("{title} {addr}"), String::new()];
+                for entry in lobby.iter() {
+                    let status = if entry.ready { "ready" } else { "waiting" };
+                    lines.push(format!("{:<16} {status:>7}", entry.name));
+                }
+                lines.push(String::new());
+                if let Some(result) = &last_result {
+                    lines.push(format!("Last round: {result}"));
+                    lines.push(String::new());
+                }
+                if watching {
+                    lines.push("Escape: stop watching".to_string());
+                } else {
+                    if in_round {
+                        lines.push("A round is on, wait for the next one".to_string());
+                    }
+                    let toggle = if ready { "not ready" } else { "ready" };
+                    lines.push(format!("Enter: {toggle}  Escape: leave"));
+                }
+                term.clear_screen()?;
+                draw_panel(&mut term, &lines, None)?;
+            }
+        }
+    }
+}
@@ -0,0 +1,504 @@
+use std::{
+    io::Write,
+    sync::{
+        mpsc::{Receiver, RecvTimeoutError},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::bail;
+use console::{Key, Term};
+
+use crate::{
+    broadcast::Broadcast,
+    input::{InputQueue, UserInput},
+    maps::Map,
+    menu::{draw_panel, Menu},
+    render::{compose, terminal_renderer, CellShape, Frame, Renderer},
+    replay::Replay,
+    settings::{GameSettings, KeyBindings, KeyPreset},
+    sim::{Board, Death, GameState, Player, SimConfig, Simulation},
+};
+
+/// A terminal frontend driving a `Simulation`
+pub struct SnakeGame<'a> {
+    term: Term,
+    input_rcv: &'a Receiver<Key>,
+    settings: &'a GameSettings,
+    sim: Simulation,
+    renderer: Box<dyn Renderer>,
+    /// Terminal size as of the last frame, as (rows, columns)
+    term_size: (u16, u16),
+}
+
+impl<'a> SnakeGame<'a> {
+    pub fn new(
+        term: Term,
+        input_rcv: &'a Receiver<Key>,
+        settings: &'a GameSettings,
+        map: Option<Arc<Map>>,
+        seed: u64,
+        players: usize,
+    ) -> anyhow::Result<Self> {
+        let board = game_board(&term, settings, map.as_deref())?;
+        let sim = Simulation::new(SimConfig {
+            board,
+            starting_length: settings.starting_length,
+            seed,
+            progressive: settings.progressive,
+            map,
+            players,
+        });
+
+        Ok(SnakeGame {
+            term_size: term.size(),
+            renderer: terminal_renderer(term.clone(), settings.plain),
+            term,
+            input_rcv,
+            settings,
+            sim,
+        })
+    }
+
+    fn frame(&self) -> Frame {
+        compose(&self.sim, &self.settings.colors, self.settings.cell_shape)
+    }
+
+    fn render(&mut self) -> anyhow::Result<()> {
+        let frame = self.frame();
+        self.renderer.draw(&frame)
+    }
+
+    /// Whether the terminal's size changed since the last frame
+    fn resized(&self) -> bool {
+        self.term.size() != self.term_size
+    }
+
+    /// Redraws the game from scratch after the terminal was resized, first
+    /// waiting for it to grow back if the board no longer fits. Returns
+    /// `false` if a player gave up with Escape or a pause key meanwhile.
+    fn handle_resize(&mut self, keys: &[KeyBindings]) -> anyhow::Result<bool> {
+        let fits = wait_for_room(
+            &self.term,
+            self.input_rcv,
+            self.sim.board(),
+            self.settings.cell_shape,
+            |key| {
+                *key == Key::Escape
+                    || keys
+                        .iter()
+                        .any(|keys| keys.input_for(key) == UserInput::Pause)
+            },
+        )?;
+        if !fits {
+            return Ok(false);
+        }
+        self.term_size = self.term.size();
+        self.renderer.invalidate();
+        self.render()?;
+
+        Ok(true)
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.sim.seed()
+    }
+
+    /// Starts the current game over from the beginning
+    pub fn restart(&mut self) {
+        self.sim = Simulation::new(self.sim.config().clone());
+    }
+
+    fn show_settings(&mut self) -> anyhow::Result<()> {
+        let mut lines = vec!["Settings".to_string(), String::new()];
+        lines.push(format!("Seed: {}", self.sim.seed()));
+        lines.extend(self.settings.describe());
+        lines.push(String::new());
+        lines.push("Press any key".to_string());
+        self.render()?;
+        draw_panel(&mut self.term, &lines, None)?;
+        self.renderer.invalidate();
+        self.input_rcv.recv()?;
+
+        Ok(())
+    }
+
+    /// Shows the pause menu over the frozen game until the player picks an action
+    fn pause(&mut self) -> anyhow::Result<PauseAction> {
+        let mut menu = Menu::new(
+            "Paused",
+            vec![
+                ("Resume".to_string(), PauseItem::Resume),
+                ("Restart".to_string(), PauseItem::Restart),
+                ("Settings".to_string(), PauseItem::Settings),
+                ("Quit".to_string(), PauseItem::Quit),
+            ],
+        );
+        loop {
+            self.render()?;
+            let item = menu.run(&mut self.term, self.input_rcv)?;
+            // the menu drew over the board
+            self.renderer.invalidate();
+            match item {
+                Some(PauseItem::Settings) => self.show_settings()?,
+                Some(PauseItem::Restart) => return Ok(PauseAction::Restart),
+                Some(PauseItem::Quit) => return Ok(PauseAction::Quit),
+                Some(PauseItem::Resume) | None => return Ok(PauseAction::Resume),
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PauseItem {
+    Resume,
+    Restart,
+    Settings,
+    Quit,
+}
+
+/// What the player chose to do from the pause menu
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PauseAction {
+    Resume,
+    Restart,
+    /// Give up the game and go back to the title menu
+    Quit,
+}
+
+/// The board a game with these settings is played on, checking that it fits
+/// in the terminal.
+///
+/// Maps have a fixed size of their own, otherwise the board is the size set
+/// in the settings or, failing that, fills the terminal.
+pub fn game_board(
+    term: &Term,
+    settings: &GameSettings,
+    map: Option<&Map>,
+) -> anyhow::Result<Board> {
+    let (ht, wt) = term.size();
+    let size = match map {
+        Some(map) => Some((map.width, map.height)),
+        None => settings.board.map(|size| (size.width, size.height)),
+    };
+    let (width, height) = match size {
+        Some((width, height)) => (width + 2, height + 2),
+        // however small the terminal, the playfield is at least 2x2, so a tiny
+        // terminal is turned away below like any other that's too small
+        None => {
+            let (width, height) = settings.cell_shape.board_size(wt as usize, ht as usize);
+            (width.max(4), height.max(4))
+        }
+    };
+    let board = Board::new(width, height, settings.wall_mode);
+    if !board_fits(term, board, settings.cell_shape) {
+        let (need_w, need_h) = settings.cell_shape.screen_size(board.width, board.height);
+        bail!(
+            "A {}x{} board needs a {need_w}x{need_h} terminal, but this one is {wt}x{ht}",
+            board.width - 2,
+            board.height - 2,
+        );
+    }
+
+    Ok(board)
+}
+
+/// Whether a board fits in the terminal as it is now
+fn board_fits(term: &Term, board: Board, shape: CellShape) -> bool {
+    let (ht, wt) = term.size();
+    let (width, height) = shape.screen_size(board.width, board.height);
+    width <= wt as usize && height <= ht as usize
+}
+
+/// Shows a "terminal too small" message until the terminal is resized to fit
+/// `board`, dropping any other keys pressed meanwhile. Returns `false` if the
+/// player gave up waiting with a key that `quits`.
+pub fn wait_for_room(
+    term: &Term,
+    input_rcv: &Receiver<Key>,
+    board: Board,
+    shape: CellShape,
+    quits: impl Fn(&Key) -> bool,
+) -> anyhow::Result<bool> {
+    let (need_w, need_h) = shape.screen_size(board.width, board.height);
+    let mut shown = None;
+    while !board_fits(term, board, shape) {
+        let (ht, wt) = term.size();
+        if shown != Some((ht, wt)) {
+            term.clear_screen()?;
+            term.write_line("Terminal too small")?;
+            term.write_line(&format!("Need {need_w}x{need_h}, have {wt}x{ht}"))?;
+            term.write_line("Escape to quit")?;
+            shown = Some((ht, wt));
+        }
+        match input_rcv.recv_timeout(Duration::from_millis(50)) {
+            Ok(key) if quits(&key) => return Ok(false),
+            Ok(_) | Err(RecvTimeoutError::Timeout) => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    Ok(true)
+}
+
+/// How players are named on screen, counting from 1
+pub fn player_name(player: usize) -> String {
+    format!("Player {}", player + 1)
+}
+
+/// The keys each player steers with. Two players share the keyboard, one on
+/// the arrow keys and the other on WASD, and either one can pause.
+fn player_keys(settings: &GameSettings, players: usize) -> anyhow::Result<Vec<KeyBindings>> {
+    Ok(match players {
+        1 => vec![settings.keys.clone()],
+        2 => vec![
+            KeyBindings::from_preset(KeyPreset::Arrows),
+            KeyBindings::from_preset(KeyPreset::Wasd),
+        ],
+        _ => bail!("Only one or two players can share a keyboard, not {players}"),
+    })
+}
+
+/// Writes the final message for a finished game at the current cursor position
+pub fn show_outcome(term: &mut Term, sim: &Simulation, state: GameState) -> anyhow::Result<()> {
+    match state {
+        GameState::Over(death) => {
+            let msg = format!("Game Over: {}  {death}", sim.score());
+            term.write_all(msg.as_bytes())?;
+        }
+        GameState::Continue => {}
+        GameState::Win => {
+            let msg = format!("You Win! Score: {}", sim.score());
+            term.write_all(msg.as_bytes())?;
+        }
+        GameState::RoundOver { winner } => {
+            let msg = match winner {
+                Some(winner) => format!("{} wins!", player_name(winner)),
+                None => "Draw!".to_string(),
+            };
+            term.write_all(msg.as_bytes())?;
+        }
+    }
+
+    Ok(())
+}
+
+/// How a call to `play` ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GameEnd {
+    Finished(GameState),
+    /// The player quit from the pause menu or while waiting for the terminal
+    /// to fit, back to the title menu
+    Quit,
+}
+
+/// Formats a duration as minutes and seconds, e.g. `2:05`
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    format!("{}:{:02}", secs / 60, secs % 60)
+}
+
+/// How one player did in a finished game
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PlayerSummary {
+    pub score: usize,
+    pub length: usize,
+    pub apples: usize,
+    pub death: Option<Death>,
+}
+
+impl PlayerSummary {
+    fn new(player: &Player) -> Self {
+        PlayerSummary {
+            score: player.score,
+            length: player.snake.body.len(),
+            apples: player.apples_eaten,
+            death: player.death,
+        }
+    }
+}
+
+/// How a game went, for showing once it's over
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GameSummary {
+    /// Every player, in order
+    pub players: Vec<PlayerSummary>,
+    /// Time spent playing, not counting pauses or waiting for the terminal to fit
+    pub duration: Duration,
+    pub seed: u64,
+}
+
+impl GameSummary {
+    fn new(sim: &Simulation, duration: Duration) -> Self {
+        GameSummary {
+            players: sim.players().iter().map(PlayerSummary::new).collect(),
+            duration,
+            seed: sim.seed(),
+        }
+    }
+}
+
+impl std::fmt::Display for GameSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.players.as_slice() {
+            [player] => write!(f, "Score: {}  Length: {}  ", player.score, player.length)?,
+            players => {
+                for (i, player) in players.iter().enumerate() {
+                    write!(f, "{}: {}  ", player_name(i), player.score)?;
+                }
+            }
+        }
+        write!(
+            f,
+            "Time: {}  Seed: {}",
+            format_duration(self.duration),
+            self.seed
+        )?;
+        if let [PlayerSummary {
+            death: Some(death), ..
+        }] = self.players.as_slice()
+        {
+            write!(f, "  {death}")?;
+        }
+
+        Ok(())
+    }
+}
+
+pub struct PlayResult {
+    pub end: GameEnd,
+    pub summary: GameSummary,
+    pub replay: Replay,
+    /// What was on screen when the game ended
+    pub last_frame: Frame,
+}
+
+/// Ends a game the player gave up on part way through
+fn quit(
+    game: &SnakeGame,
+    broadcast: Option<&Broadcast>,
+    replay: Replay,
+    played: Duration,
+) -> PlayResult {
+    if let Some(broadcast) = broadcast {
+        broadcast.finish(GameState::Continue);
+    }
+    PlayResult {
+        end: GameEnd::Quit,
+        summary: GameSummary::new(&game.sim, played),
+        replay,
+        last_frame: game.frame(),
+    }
+}
+
+/// Runs a game until it finishes or the player quits, returning a recording
+/// of it. With two players, they share the keyboard. Anyone watching through
+/// `broadcast` is shown the game as it goes.
+pub fn play(
+    term: Term,
+    input_rcv: &Receiver<Key>,
+    settings: &GameSettings,
+    map: Option<Arc<Map>>,
+    seed: u64,
+    players: usize,
+    broadcast: Option<&Broadcast>,
+) -> anyhow::Result<PlayResult> {
+    let keys = player_keys(settings, players)?;
+    let mut game = SnakeGame::new(term, input_rcv, settings, map, seed, players)?;
+    if let Some(broadcast) = broadcast {
+        broadcast.start(&game.sim);
+    }
+    let mut replay = Replay::new(game.sim.config().clone(), settings.base_tick_ms());
+    let mut turns: Vec<InputQueue> = keys.iter().map(|_| InputQueue::new()).collect();
+    let mut played = Duration::ZERO;
+
+    'game: loop {
+        let mut start = Instant::now();
+        // the terminal's size is checked once a tick
+        if game.resized() {
+            if !game.handle_resize(&keys)? {
+                return Ok(quit(&game, broadcast, replay, played));
+            }
+            // the tick starts over once the terminal fits, so time spent
+            // waiting for it counts towards neither the game nor the tick
+            start = Instant::now();
+        }
+        game.render()?;
+        let tick = Duration::from_secs_f64(settings.tick_secs(game.sim.level()));
+        while let Some(remaining) = tick.checked_sub(start.elapsed()) {
+            let key = match game.input_rcv.recv_timeout(remaining) {
+                Ok(key) => key,
+                Err(RecvTimeoutError::Timeout) => break,
+                Err(e) => return Err(e.into()),
+            };
+            // the first player the key is bound for gets it
+            let Some((player, input)) = keys
+                .iter()
+                .map(|keys| keys.input_for(&key))
+                .enumerate()
+                .find(|(_, input)| *input != UserInput::Unknown)
+            else {
+                continue;
+            };
+            match input {
+                UserInput::Pause => {
+                    // time in the pause menu counts towards neither the game nor the tick
+                    played += start.elapsed();
+                    let action = game.pause()?;
+                    start = Instant::now();
+                    match action {
+                        PauseAction::Resume => game.render()?,
+                        PauseAction::Restart => {
+                            game.restart();
+                            if let Some(broadcast) = broadcast {
+                                broadcast.start(&game.sim);
+                            }
+                            replay =
+                                Replay::new(game.sim.config().clone(), settings.base_tick_ms());
+                            turns.iter_mut().for_each(InputQueue::clear);
+                            played = Duration::ZERO;
+                            continue 'game;
+                        }
+                        PauseAction::Quit => {
+                            return Ok(quit(&game, broadcast, replay, played));
+                        }
+                    }
+                }
+                input => {
+                    if let Some(dir) = input.dir() {
+                        let heading = game.sim.players()[player].snake.head().dir;
+                        turns[player].push(dir, heading);
+                    }
+                }
+            }
+        }
+        played += start.elapsed();
+        let inputs: Vec<UserInput> = turns
+            .iter_mut()
+            .zip(game.sim.players())
+            .map(|(turns, player)| turns.next(player.snake.head().dir).into())
+            .collect();
+        for input in inputs.iter() {
+            replay.push(*input);
+        }
+        let state = game.sim.update(&inputs);
+        if let GameState::Over(death) = state {
+            replay.death = Some(death);
+        }
+        if let Some(broadcast) = broadcast {
+            broadcast.update(&game.sim);
+        }
+        if state != GameState::Continue {
+            if let Some(broadcast) = broadcast {
+                broadcast.finish(state);
+            }
+            return Ok(PlayResult {
+                end: GameEnd::Finished(state),
+                summary: GameSummary::new(&game.sim, played),
+                replay,
+                last_frame: game.frame(),
+            });
+        }
+    }
+}
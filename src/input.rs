@@ -0,0 +1,172 @@
+use std::{
+    collections::VecDeque,
+    sync::mpsc::{channel, Receiver},
+    thread,
+};
+
+use console::{Key, Term};
+
+use crate::snake::Dir;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UserInput {
+    Unknown,
+    Pause,
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+impl UserInput {
+    /// The direction this input steers the snake in, if any
+    pub fn dir(self) -> Option<Dir> {
+        match self {
+            UserInput::Up => Some(Dir::Up),
+            UserInput::Down => Some(Dir::Down),
+            UserInput::Left => Some(Dir::Left),
+            UserInput::Right => Some(Dir::Right),
+            UserInput::Unknown | UserInput::Pause => None,
+        }
+    }
+}
+
+impl From<Dir> for UserInput {
+    fn from(value: Dir) -> Self {
+        match value {
+            Dir::Up => Self::Up,
+            Dir::Down => Self::Down,
+            Dir::Left => Self::Left,
+            Dir::Right => Self::Right,
+        }
+    }
+}
+
+/// How many turns can be queued up ahead of the snake
+pub const INPUT_QUEUE_LEN: usize = 3;
+
+/// Turns queued up by the player, applied one per tick.
+///
+/// Keys pressed faster than the tick rate aren't lost, so quick maneuvers like
+/// a U-turn (e.g. Up then Left while heading Right) play out over the next
+/// two ticks.
+#[derive(Debug, Default)]
+pub struct InputQueue {
+    turns: VecDeque<Dir>,
+}
+
+impl InputQueue {
+    pub fn new() -> Self {
+        InputQueue {
+            turns: VecDeque::with_capacity(INPUT_QUEUE_LEN),
+        }
+    }
+
+    /// Queues a turn, given the snake's current heading. Turns that wouldn't
+    /// change direction after the ones already queued, or would reverse into
+    /// the body, are dropped, as are turns past the queue's capacity.
+    pub fn push(&mut self, dir: Dir, heading: Dir) {
+        let last = self.turns.back().copied().unwrap_or(heading);
+        if dir == last || dir.is_opposite(last) || self.turns.len() >= INPUT_QUEUE_LEN {
+            return;
+        }
+        self.turns.push_back(dir);
+    }
+
+    /// The direction to move in this tick
+    pub fn next(&mut self, heading: Dir) -> Dir {
+        while let Some(dir) = self.turns.pop_front() {
+            if dir != heading && !dir.is_opposite(heading) {
+                return dir;
+            }
+        }
+
+        heading
+    }
+
+    pub fn clear(&mut self) {
+        self.turns.clear();
+    }
+}
+
+/// Reads keys from `term` on a background thread, forwarding them to the
+/// returned channel.
+///
+/// The thread stops once the terminal can't be read from any more, or after
+/// the next key press once the receiver has been dropped.
+pub fn spawn_input_thread(term: Term) -> Receiver<Key> {
+    let (tx, rx) = channel();
+    thread::spawn(move || {
+        while let Ok(key) = term.read_key() {
+            if tx.send(key).is_err() {
+                break;
+            }
+        }
+    });
+
+    rx
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Plays queued turns out over `ticks` ticks, starting from `heading`
+    fn play_out(queue: &mut InputQueue, mut heading: Dir, ticks: usize) -> Vec<Dir> {
+        let mut moves = Vec::new();
+        for _ in 0..ticks {
+            heading = queue.next(heading);
+            moves.push(heading);
+        }
+        moves
+    }
+
+    #[test]
+    fn quick_u_turn_plays_out_over_two_ticks() {
+        let mut queue = InputQueue::new();
+        queue.push(Dir::Up, Dir::Right);
+        queue.push(Dir::Left, Dir::Right);
+
+        assert_eq!(
+            play_out(&mut queue, Dir::Right, 3),
+            [Dir::Up, Dir::Left, Dir::Left]
+        );
+    }
+
+    #[test]
+    fn reversing_is_dropped() {
+        let mut queue = InputQueue::new();
+        queue.push(Dir::Left, Dir::Right);
+        queue.push(Dir::Up, Dir::Right);
+        queue.push(Dir::Down, Dir::Right);
+
+        assert_eq!(play_out(&mut queue, Dir::Right, 2), [Dir::Up, Dir::Up]);
+    }
+
+    #[test]
+    fn repeated_turns_are_only_queued_once() {
+        let mut queue = InputQueue::new();
+        queue.push(Dir::Right, Dir::Right);
+        queue.push(Dir::Down, Dir::Right);
+        queue.push(Dir::Down, Dir::Right);
+        queue.push(Dir::Left, Dir::Right);
+
+        assert_eq!(
+            play_out(&mut queue, Dir::Right, 3),
+            [Dir::Down, Dir::Left, Dir::Left]
+        );
+    }
+
+    #[test]
+    fn turns_past_the_capacity_are_dropped() {
+        let mut queue = InputQueue::new();
+        let turns = [Dir::Up, Dir::Left, Dir::Down, Dir::Right, Dir::Up];
+        for dir in turns {
+            queue.push(dir, Dir::Right);
+        }
+
+        let moves = play_out(&mut queue, Dir::Right, INPUT_QUEUE_LEN + 1);
+        assert_eq!(moves[..INPUT_QUEUE_LEN], turns[..INPUT_QUEUE_LEN]);
+        assert_eq!(moves[INPUT_QUEUE_LEN], turns[INPUT_QUEUE_LEN - 1]);
+    }
+}
@@ -0,0 +1,18 @@
+pub mod app;
+pub mod broadcast;
+pub mod client;
+pub mod game;
+pub mod input;
+pub mod maps;
+pub mod menu;
+pub mod net;
+pub mod render;
+pub mod replay;
+pub mod scores;
+pub mod server;
+pub mod settings;
+pub mod sim;
+pub mod snake;
+pub mod terminal;
+#[cfg(test)]
+mod test_util;
@@ -1,28 +1,144 @@
-mod snake;
-
-use std::io::Write;
+use std::path::PathBuf;
 
+use anyhow::{anyhow, Context};
 use console::Term;
-use snake::play;
+use rusty_snake::{
+    app::App,
+    broadcast::Broadcast,
+    client::{play_online, watch},
+    maps::Map,
+    net::clean_name,
+    render::CellShape,
+    replay::{play_replay, PlaybackSpeed, Replay},
+    settings::{flag_value, GameSettings, RuleOverrides},
+    terminal::TerminalGuard,
+};
 
-#[allow(dead_code)]
-fn main_menu(mut term: Term) -> anyhow::Result<()> {
-    let (height, width) = term.size();
+struct Args {
+    seed: Option<u64>,
+    record: Option<PathBuf>,
+    replay: Option<PathBuf>,
+    speed: PlaybackSpeed,
+    config: Option<PathBuf>,
+    rules: RuleOverrides,
+    plain: bool,
+    cell_shape: Option<CellShape>,
+    maps: Vec<PathBuf>,
+    connect: Option<String>,
+    name: Option<String>,
+    watch: Option<String>,
+    broadcast: Option<String>,
+}
 
-    term.move_cursor_to(width as usize / 2, height as usize / 2)?;
-    term.write_all("SNAKE".as_bytes())?;
+fn parse_args() -> anyhow::Result<Args> {
+    let mut args = Args {
+        seed: None,
+        record: None,
+        replay: None,
+        speed: PlaybackSpeed::Normal,
+        config: None,
+        rules: RuleOverrides::default(),
+        plain: false,
+        cell_shape: None,
+        maps: Vec::new(),
+        connect: None,
+        name: None,
+        watch: None,
+        broadcast: None,
+    };
+    let mut argv = std::env::args().skip(1);
+    while let Some(arg) = argv.next() {
+        match arg.as_str() {
+            "--seed" => {
+                let val = flag_value(&mut argv, &arg)?;
+                args.seed = Some(val.parse().context("--seed must be an unsigned integer")?);
+            }
+            "--record" => args.record = Some(flag_value(&mut argv, &arg)?.into()),
+            "--replay" => args.replay = Some(flag_value(&mut argv, &arg)?.into()),
+            "--speed" => args.speed = flag_value(&mut argv, &arg)?.parse()?,
+            "--config" => args.config = Some(flag_value(&mut argv, &arg)?.into()),
+            "--plain" => args.plain = true,
+            "--cells" => args.cell_shape = Some(flag_value(&mut argv, &arg)?.parse()?),
+            "--map" => args.maps.push(flag_value(&mut argv, &arg)?.into()),
+            "--connect" => args.connect = Some(flag_value(&mut argv, &arg)?),
+            "--name" => args.name = Some(flag_value(&mut argv, &arg)?),
+            "--watch" => args.watch = Some(flag_value(&mut argv, &arg)?),
+            "--broadcast" => args.broadcast = Some(flag_value(&mut argv, &arg)?),
+            _ if args.rules.parse_flag(&arg, &mut argv)? => {}
+            _ => return Err(anyhow!("Unrecognized argument: {arg}")),
+        }
+    }
 
-    Ok(())
+    Ok(args)
+}
+
+/// Loads the config file, then applies any overrides given on the command line
+fn load_settings(args: &Args) -> anyhow::Result<GameSettings> {
+    let mut settings = match args.config.clone().or_else(GameSettings::default_path) {
+        Some(path) => GameSettings::load(&path)?,
+        None => GameSettings::default(),
+    };
+    args.rules.apply(&mut settings);
+    if args.plain {
+        settings.plain = true;
+    }
+    if let Some(cell_shape) = args.cell_shape {
+        settings.cell_shape = cell_shape;
+    }
+    settings.validate()?;
+
+    Ok(settings)
 }
 
 fn main() -> anyhow::Result<()> {
-    let term = Term::stdout();
-    term.clear_screen()?;
-    term.hide_cursor()?;
-    // main_menu(&mut term);
-    play(term.clone())?;
+    let args = parse_args()?;
+    let settings = load_settings(&args)?;
+    let replay = args.replay.as_deref().map(Replay::load).transpose()?;
+    let maps = args
+        .maps
+        .iter()
+        .map(|path| Map::load(path))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let broadcast = args.broadcast.as_deref().map(Broadcast::bind).transpose()?;
+    if let Some(broadcast) = &broadcast {
+        eprintln!("Broadcasting on {}", broadcast.local_addr());
+    }
 
-    term.show_cursor()?;
+    let term = Term::stdout();
+    let last_game = {
+        let _guard = TerminalGuard::new(term.clone())?;
+        if let Some(replay) = replay {
+            play_replay(term.clone(), &replay, args.speed, &settings)?;
+            None
+        } else if let Some(addr) = &args.connect {
+            let name = args
+                .name
+                .or_else(|| std::env::var("USER").ok())
+                .unwrap_or_default();
+            play_online(term.clone(), addr, &clean_name(&name), &settings)?;
+            None
+        } else if let Some(addr) = &args.watch {
+            watch(term.clone(), addr, &settings)?;
+            None
+        } else {
+            let config_path = args.config.or_else(GameSettings::default_path);
+            let mut app = App::new(
+                term.clone(),
+                settings,
+                config_path,
+                args.seed,
+                args.record,
+                maps,
+                broadcast,
+            )?;
+            app.run()?;
+            app.last_game()
+        }
+    };
+    // printed once back on the normal screen so it stays in the scrollback
+    if let Some(summary) = last_game {
+        println!("{summary}");
+    }
 
     Ok(())
 }
@@ -0,0 +1,224 @@
+use std::{collections::BTreeSet, fs, path::Path, str::FromStr};
+
+use anyhow::{anyhow, bail, Context};
+
+use crate::snake::{Dir, TermPoint};
+
+/// Maps compiled into the binary
+const BUILTIN_MAPS: &[&str] = &[
+    include_str!("../maps/box.txt"),
+    include_str!("../maps/cross.txt"),
+    include_str!("../maps/pillars.txt"),
+    include_str!("../maps/rooms.txt"),
+];
+
+/// A playfield layout with internal walls.
+///
+/// Maps are plain text: an optional `name: <name>` line, then one line per row
+/// of the playfield (the border around it is implicit). Lines starting with
+/// `;` are comments. In the grid,
+///
+/// - `#` is a wall
+/// - `.` is an empty cell
+/// - `@` is an empty cell apples may appear on. If a map has any of these,
+///   apples only ever appear on them
+/// - `^`, `v`, `<` or `>` is where the snake's head starts, facing that way
+///
+/// All positions are in board coordinates, i.e. offset by the border.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Map {
+    pub name: String,
+    /// Size of the playfield, not counting the border
+    pub width: usize,
+    pub height: usize,
+    pub walls: BTreeSet<TermPoint>,
+    pub spawn: TermPoint,
+    pub spawn_dir: Dir,
+    pub apple_spots: Vec<TermPoint>,
+    /// The grid rows the map was parsed from
+    pub rows: Vec<String>,
+}
+
+impl Map {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read map {}", path.display()))?;
+        let mut map: Map = contents
+            .parse()
+            .with_context(|| format!("Invalid map {}", path.display()))?;
+        if map.name.is_empty() {
+            map.name = path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .unwrap_or_default();
+        }
+
+        Ok(map)
+    }
+
+    /// The maps that ship with the game
+    pub fn builtins() -> Vec<Self> {
+        BUILTIN_MAPS
+            .iter()
+            .map(|contents| contents.parse().expect("Built-in maps are valid"))
+            .collect()
+    }
+
+    /// Builds a map from its grid rows, checking that it's playable
+    pub fn from_rows(name: String, rows: Vec<String>) -> anyhow::Result<Self> {
+        let height = rows.len();
+        let width = rows.first().map_or(0, |row| row.chars().count());
+        if width < 2 || height < 2 {
+            bail!("Maps must be at least 2x2");
+        }
+
+        let mut walls = BTreeSet::new();
+        let mut apple_spots = Vec::new();
+        let mut spawn = None;
+        for (r, row) in rows.iter().enumerate() {
+            if row.chars().count() != width {
+                bail!("Row {} is not {width} cells wide", r + 1);
+            }
+            for (c, cell) in row.chars().enumerate() {
+                let pos = TermPoint::new(r + 1, c + 1);
+                let dir = match cell {
+                    '#' => {
+                        walls.insert(pos);
+                        continue;
+                    }
+                    '.' => continue,
+                    '@' => {
+                        apple_spots.push(pos);
+                        continue;
+                    }
+                    '^' => Dir::Up,
+                    'v' => Dir::Down,
+                    '<' => Dir::Left,
+                    '>' => Dir::Right,
+                    _ => bail!("Unknown cell '{cell}' at row {}, column {}", r + 1, c + 1),
+                };
+                if spawn.replace((pos, dir)).is_some() {
+                    bail!("Maps must have exactly one spawn point");
+                }
+            }
+        }
+        let (spawn, spawn_dir) = spawn.ok_or_else(|| anyhow!("Map has no spawn point"))?;
+        if walls.len() + 1 >= width * height {
+            bail!("Map has no room for apples");
+        }
+        // the snake's first move mustn't run it straight into something
+        let (row, col) = (spawn.row, spawn.col);
+        let ahead = match spawn_dir {
+            Dir::Up => TermPoint::new(row - 1, col),
+            Dir::Down => TermPoint::new(row + 1, col),
+            Dir::Left => TermPoint::new(row, col - 1),
+            Dir::Right => TermPoint::new(row, col + 1),
+        };
+        let on_border = !(1..=height).contains(&ahead.row) || !(1..=width).contains(&ahead.col);
+        if on_border || walls.contains(&ahead) {
+            bail!("The spawn point faces straight into a wall");
+        }
+
+        Ok(Map {
+            name,
+            width,
+            height,
+            walls,
+            spawn,
+            spawn_dir,
+            apple_spots,
+            rows,
+        })
+    }
+}
+
+impl FromStr for Map {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut name = String::new();
+        let mut rows = Vec::new();
+        for line in s.lines() {
+            let line = line.trim_end();
+            if line.starts_with(';') || line.is_empty() {
+                continue;
+            }
+            match line.strip_prefix("name:") {
+                Some(val) if rows.is_empty() => name = val.trim().to_string(),
+                _ => rows.push(line.to_string()),
+            }
+        }
+
+        Map::from_rows(name, rows)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_error(map: &str) -> String {
+        map.parse::<Map>().unwrap_err().to_string()
+    }
+
+    #[test]
+    fn builtin_maps_parse() {
+        for contents in BUILTIN_MAPS {
+            let map: Map = contents.parse().unwrap();
+            assert!(!map.name.is_empty());
+            assert!(!map.walls.contains(&map.spawn));
+        }
+    }
+
+    #[test]
+    fn parses_walls_spawn_and_apple_spots() {
+        let map: Map = "name: Test\n; a comment\n#..\n.>@\n".parse().unwrap();
+
+        assert_eq!(map.name, "Test");
+        assert_eq!((map.width, map.height), (3, 2));
+        assert_eq!(map.walls, BTreeSet::from([TermPoint::new(1, 1)]));
+        assert_eq!(
+            (map.spawn, map.spawn_dir),
+            (TermPoint::new(2, 2), Dir::Right)
+        );
+        assert_eq!(map.apple_spots, [TermPoint::new(2, 3)]);
+    }
+
+    #[test]
+    fn ragged_rows_are_rejected() {
+        assert_eq!(parse_error("...\n.>\n"), "Row 2 is not 3 cells wide");
+    }
+
+    #[test]
+    fn exactly_one_spawn_point_is_needed() {
+        assert_eq!(parse_error("...\n...\n"), "Map has no spawn point");
+        assert_eq!(
+            parse_error(">..\n..<\n"),
+            "Maps must have exactly one spawn point"
+        );
+    }
+
+    #[test]
+    fn unknown_cells_are_rejected() {
+        assert_eq!(
+            parse_error("...\n.>x\n"),
+            "Unknown cell 'x' at row 2, column 3"
+        );
+    }
+
+    #[test]
+    fn spawning_facing_a_wall_is_rejected() {
+        let error = "The spawn point faces straight into a wall";
+        assert_eq!(parse_error("...\n.>#\n"), error);
+        assert_eq!(parse_error("...\n..>\n"), error);
+        assert_eq!(parse_error(".^.\n...\n"), error);
+        assert!("...\n<..\n".parse::<Map>().is_err());
+        assert!("...\n.<.\n".parse::<Map>().is_ok());
+    }
+
+    #[test]
+    fn maps_need_room_for_an_apple() {
+        assert_eq!(parse_error("#>\n##\n"), "Map has no room for apples");
+        assert_eq!(parse_error(">\n"), "Maps must be at least 2x2");
+    }
+}
@@ -0,0 +1,128 @@
+use std::{io::Write, sync::mpsc::Receiver};
+
+use console::{style, Key, Term};
+
+/// Draws `lines` inside a bordered box centered on the terminal, highlighting
+/// the line at `highlight` if there is one
+pub fn draw_panel(
+    term: &mut Term,
+    lines: &[String],
+    highlight: Option<usize>,
+) -> anyhow::Result<()> {
+    let (ht, wt) = term.size();
+    let inner_width = lines
+        .iter()
+        .map(|l| console::measure_text_width(l))
+        .max()
+        .unwrap_or(0)
+        + 2;
+    let box_width = inner_width + 2;
+    let box_height = lines.len() + 2;
+    let left = (wt as usize).saturating_sub(box_width) / 2;
+    let top = (ht as usize).saturating_sub(box_height) / 2;
+
+    term.move_cursor_to(left, top)?;
+    term.write_all(format!("┌{}┐", "─".repeat(inner_width)).as_bytes())?;
+    for (i, line) in lines.iter().enumerate() {
+        term.move_cursor_to(left, top + 1 + i)?;
+        let padded = console::pad_str(line, inner_width - 2, console::Alignment::Center, None);
+        let text = if highlight == Some(i) {
+            format!("{}", style(format!(" {padded} ")).black().on_white())
+        } else {
+            format!(" {padded} ")
+        };
+        term.write_all(format!("│{text}│").as_bytes())?;
+    }
+    term.move_cursor_to(left, top + box_height - 1)?;
+    term.write_all(format!("└{}┘", "─".repeat(inner_width)).as_bytes())?;
+
+    Ok(())
+}
+
+/// A vertical list of choices navigated with the arrow keys
+pub struct Menu<T> {
+    title: String,
+    /// Lines shown between the title and the items
+    details: Vec<String>,
+    items: Vec<(String, T)>,
+    selected: usize,
+}
+
+impl<T: Copy> Menu<T> {
+    pub fn new(title: impl Into<String>, items: Vec<(String, T)>) -> Self {
+        Menu {
+            title: title.into(),
+            details: Vec::new(),
+            items,
+            selected: 0,
+        }
+    }
+
+    pub fn with_details(mut self, details: Vec<String>) -> Self {
+        self.details = details;
+        self
+    }
+
+    /// Starts with the item at `idx` selected instead of the first
+    pub fn with_selected(mut self, idx: usize) -> Self {
+        self.selected = idx.min(self.items.len().saturating_sub(1));
+        self
+    }
+
+    pub fn selected(&self) -> usize {
+        self.selected
+    }
+
+    fn draw(&self, term: &mut Term) -> anyhow::Result<()> {
+        let mut lines = vec![self.title.clone(), String::new()];
+        if !self.details.is_empty() {
+            lines.extend(self.details.iter().cloned());
+            lines.push(String::new());
+        }
+        let first_item = lines.len();
+        lines.extend(self.items.iter().map(|(label, _)| label.clone()));
+        draw_panel(term, &lines, Some(first_item + self.selected))
+    }
+
+    /// Shows the menu until an item is chosen with Enter, returning its value,
+    /// or `None` if the menu is dismissed with Escape
+    pub fn run(&mut self, term: &mut Term, input_rcv: &Receiver<Key>) -> anyhow::Result<Option<T>> {
+        loop {
+            self.draw(term)?;
+            match input_rcv.recv()? {
+                Key::ArrowUp => {
+                    self.selected = self.selected.checked_sub(1).unwrap_or(self.items.len() - 1);
+                }
+                Key::ArrowDown => self.selected = (self.selected + 1) % self.items.len(),
+                Key::Enter => return Ok(Some(self.items[self.selected].1)),
+                Key::Escape => return Ok(None),
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Asks for a line of text of up to `max_len` characters, starting from
+/// `initial`. Returns `None` if the prompt is dismissed with Escape.
+pub fn prompt_text(
+    term: &mut Term,
+    input_rcv: &Receiver<Key>,
+    title: &str,
+    initial: &str,
+    max_len: usize,
+) -> anyhow::Result<Option<String>> {
+    let mut text: String = initial.chars().take(max_len).collect();
+    loop {
+        let field = format!("{:<max_len$}", format!("{text}_"), max_len = max_len + 1);
+        draw_panel(term, &[title.to_string(), String::new(), field], Some(2))?;
+        match input_rcv.recv()? {
+            Key::Char(c) if !c.is_control() && text.chars().count() < max_len => text.push(c),
+            Key::Backspace => {
+                text.pop();
+            }
+            Key::Enter if !text.trim().is_empty() => return Ok(Some(text.trim().to_string())),
+            Key::Escape => return Ok(None),
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,664 @@
+use std::{
+    fmt::{Display, Write as _},
+    io::{BufRead, Read, Write},
+    net::{Shutdown, TcpStream},
+    str::FromStr,
+    sync::mpsc::{sync_channel, SyncSender},
+    thread,
+    time::Duration,
+};
+
+use anyhow::{anyhow, bail, Context};
+
+use crate::{
+    input::UserInput,
+    replay::{decode_cause, decode_input, encode_cause, encode_input, write_config, ConfigReader},
+    sim::{Death, Delta, GameState, PlayerDelta, PlayerState, SimConfig, Snapshot},
+    snake::{BodySegment, TermPoint},
+};
+
+/// Bumped whenever the messages below change incompatibly
+pub const PROTOCOL_VERSION: u32 = 1;
+/// Where the server listens unless told otherwise
+pub const DEFAULT_ADDR: &str = "127.0.0.1:7797";
+pub const MAX_NAME_LEN: usize = 16;
+/// How long a connection can go without taking anything it's sent before
+/// it's dropped
+pub const WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+/// How many messages can wait to be sent on a connection before it's dropped
+pub const OUTBOX_LEN: usize = 64;
+/// How long someone connecting has to say who they are
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+/// The longest line a client can send. Its messages are all short, so
+/// anything longer isn't one.
+const MAX_CLIENT_LINE: usize = 256;
+/// The longest line the server can send, room enough for a snake filling a
+/// large board
+const MAX_SERVER_LINE: usize = 1 << 20;
+
+/// Makes a name safe to send, keeping letters, digits, `-` and `_`
+pub fn clean_name(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .filter(|c| c.is_alphanumeric() || *c == '-' || *c == '_')
+        .take(MAX_NAME_LEN)
+        .collect();
+    if name.is_empty() {
+        "Player".to_string()
+    } else {
+        name
+    }
+}
+
+/// What a client sends to the server, one line each
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClientMsg {
+    /// The first message on a connection, to join the lobby
+    Hello { version: u32, name: String },
+    /// The first message on a connection, to watch without playing
+    Watch { version: u32 },
+    /// Whether the player is ready for the next round to start
+    Ready(bool),
+    /// A turn for the player's snake, applied on the next tick
+    Input(UserInput),
+}
+
+impl Display for ClientMsg {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ClientMsg::Hello { version, name } => writeln!(f, "hello {version} {name}"),
+            ClientMsg::Watch { version } => writeln!(f, "watch {version}"),
+            ClientMsg::Ready(true) => writeln!(f, "ready"),
+            ClientMsg::Ready(false) => writeln!(f, "unready"),
+            ClientMsg::Input(input) => writeln!(f, "input {}", encode_input(*input)),
+        }
+    }
+}
+
+impl FromStr for ClientMsg {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, val) = s.split_once(' ').unwrap_or((s, ""));
+        Ok(match kind {
+            "hello" => {
+                let (version, name) = val.split_once(' ').unwrap_or((val, ""));
+                ClientMsg::Hello {
+                    version: version.parse().context("Invalid protocol version")?,
+                    name: clean_name(name),
+                }
+            }
+            "watch" => ClientMsg::Watch {
+                version: val.parse().context("Invalid protocol version")?,
+            },
+            "ready" => ClientMsg::Ready(true),
+            "unready" => ClientMsg::Ready(false),
+            "input" => {
+                let mut chars = val.chars();
+                let (Some(c), None) = (chars.next(), chars.next()) else {
+                    bail!("Invalid input '{val}'");
+                };
+                ClientMsg::Input(decode_input(c)?)
+            }
+            _ => bail!("Unknown message '{kind}'"),
+        })
+    }
+}
+
+impl ClientMsg {
+    /// Reads the next message, or `None` once the connection is closed
+    pub fn read(reader: &mut impl BufRead) -> anyhow::Result<Option<Self>> {
+        match read_line(reader, MAX_CLIENT_LINE)? {
+            Some(line) => Ok(Some(line.parse()?)),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Someone waiting in the lobby
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LobbyEntry {
+    pub name: String,
+    pub ready: bool,
+}
+
+/// What the server sends to its clients
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ServerMsg {
+    /// Everyone connected, in the order they joined, and whether a round is
+    /// being played right now
+    Lobby {
+        players: Vec<LobbyEntry>,
+        in_round: bool,
+    },
+    /// A round is starting, or being joined part way through, with the client
+    /// playing as player `you` or just watching
+    Start {
+        you: Option<usize>,
+        config: SimConfig,
+        snapshot: Snapshot,
+    },
+    /// How the game changed over a tick
+    Tick(Delta),
+    /// The round is over
+    Over(GameState),
+    /// Something went wrong and the server is closing the connection
+    Error(String),
+}
+
+fn encode_point(pos: TermPoint) -> String {
+    format!("{},{}", pos.row, pos.col)
+}
+
+fn decode_point(s: &str) -> anyhow::Result<TermPoint> {
+    let (row, col) = s
+        .split_once(',')
+        .ok_or_else(|| anyhow!("Invalid position '{s}'"))?;
+    Ok(TermPoint::new(
+        row.parse().context("Invalid row")?,
+        col.parse().context("Invalid column")?,
+    ))
+}
+
+/// A segment as `<row>,<column>,<direction>`
+fn encode_segment(seg: &BodySegment) -> String {
+    format!("{},{}", encode_point(seg.pos), encode_input(seg.dir.into()))
+}
+
+fn decode_segment(s: &str) -> anyhow::Result<BodySegment> {
+    let (pos, dir) = s
+        .rsplit_once(',')
+        .ok_or_else(|| anyhow!("Invalid segment '{s}'"))?;
+    let mut chars = dir.chars();
+    let dir = match (chars.next(), chars.next()) {
+        (Some(c), None) => decode_input(c)?.dir(),
+        _ => None,
+    };
+    Ok(BodySegment {
+        pos: decode_point(pos)?,
+        dir: dir.ok_or_else(|| anyhow!("Invalid direction '{s}'"))?,
+    })
+}
+
+/// A death as `<cause>@<row>,<column>`, or `-` for none
+fn encode_death(death: Option<Death>) -> String {
+    match death {
+        Some(Death { cause, head }) => format!("{}@{}", encode_cause(cause), encode_point(head)),
+        None => "-".to_string(),
+    }
+}
+
+fn decode_death(s: &str) -> anyhow::Result<Option<Death>> {
+    if s == "-" {
+        return Ok(None);
+    }
+    let (cause, head) = s
+        .split_once('@')
+        .ok_or_else(|| anyhow!("Invalid death '{s}'"))?;
+    Ok(Some(Death {
+        cause: decode_cause(cause)?,
+        head: decode_point(head)?,
+    }))
+}
+
+/// A player's change as `<player>/<head>/<length>/<score>/<apples>/<death>`,
+/// with `-` for a head that didn't move
+fn encode_change(change: &PlayerDelta) -> String {
+    let head = match &change.head {
+        Some(head) => encode_segment(head),
+        None => "-".to_string(),
+    };
+    format!(
+        "{}/{head}/{}/{}/{}/{}",
+        change.player,
+        change.length,
+        change.score,
+        change.apples_eaten,
+        encode_death(change.death)
+    )
+}
+
+fn decode_change(s: &str) -> anyhow::Result<PlayerDelta> {
+    let fields: Vec<&str> = s.split('/').collect();
+    let [player, head, length, score, apples, death] = fields[..] else {
+        bail!("Invalid change '{s}'");
+    };
+    Ok(PlayerDelta {
+        player: player.parse().context("Invalid player")?,
+        head: match head {
+            "-" => None,
+            head => Some(decode_segment(head)?),
+        },
+        length: length.parse().context("Invalid length")?,
+        score: score.parse().context("Invalid score")?,
+        apples_eaten: apples.parse().context("Invalid apple count")?,
+        death: decode_death(death)?,
+    })
+}
+
+fn encode_state(state: GameState) -> String {
+    match state {
+        GameState::Continue => "continue".to_string(),
+        GameState::Over(death) => format!("dead {}", encode_death(Some(death))),
+        GameState::Win => "win".to_string(),
+        GameState::RoundOver {
+            winner: Some(winner),
+        } => format!("winner {winner}"),
+        GameState::RoundOver { winner: None } => "draw".to_string(),
+    }
+}
+
+fn decode_state(s: &str) -> anyhow::Result<GameState> {
+    let (kind, val) = s.split_once(' ').unwrap_or((s, ""));
+    Ok(match kind {
+        "continue" => GameState::Continue,
+        "dead" => GameState::Over(decode_death(val)?.ok_or_else(|| anyhow!("Missing death"))?),
+        "win" => GameState::Win,
+        "winner" => GameState::RoundOver {
+            winner: Some(val.parse().context("Invalid winner")?),
+        },
+        "draw" => GameState::RoundOver { winner: None },
+        _ => bail!("Unknown outcome '{s}'"),
+    })
+}
+
+impl Display for ServerMsg {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ServerMsg::Lobby { players, in_round } => {
+                write!(f, "lobby {}", if *in_round { "playing" } else { "open" })?;
+                for entry in players {
+                    let ready = if entry.ready { "ready" } else { "waiting" };
+                    write!(f, " {}:{ready}", entry.name)?;
+                }
+                writeln!(f)
+            }
+            // the only message spanning several lines, ending with `go`
+            ServerMsg::Start {
+                you,
+                config,
+                snapshot,
+            } => {
+                match you {
+                    Some(you) => writeln!(f, "start {you}")?,
+                    None => writeln!(f, "start -")?,
+                }
+                write_config(f, config)?;
+                for state in snapshot.players.iter() {
+                    let body: Vec<String> = state.body.iter().map(encode_segment).collect();
+                    writeln!(
+                        f,
+                        "player {} {} {} {}",
+                        state.score,
+                        state.apples_eaten,
+                        encode_death(state.death),
+                        body.join(";")
+                    )?;
+                }
+                writeln!(f, "apple {}", encode_point(snapshot.apple))?;
+                writeln!(f, "go")
+            }
+            ServerMsg::Tick(delta) => {
+                write!(f, "tick {}", encode_point(delta.apple))?;
+                for change in delta.players.iter() {
+                    write!(f, " {}", encode_change(change))?;
+                }
+                writeln!(f)
+            }
+            ServerMsg::Over(state) => writeln!(f, "over {}", encode_state(*state)),
+            ServerMsg::Error(msg) => writeln!(f, "error {msg}"),
+        }
+    }
+}
+
+impl ServerMsg {
+    /// Reads the next message, or `None` once the connection is closed
+    pub fn read(reader: &mut impl BufRead) -> anyhow::Result<Option<Self>> {
+        let Some(line) = read_line(reader, MAX_SERVER_LINE)? else {
+            return Ok(None);
+        };
+        let (kind, val) = line.split_once(' ').unwrap_or((&line, ""));
+        let msg = match kind {
+            "lobby" => {
+                let mut fields = val.split(' ');
+                let in_round = match fields.next() {
+                    Some("playing") => true,
+                    Some("open") => false,
+                    _ => bail!("Invalid lobby '{val}'"),
+                };
+                let players = fields
+                    .map(|field| {
+                        let (name, ready) = field
+                            .rsplit_once(':')
+                            .ok_or_else(|| anyhow!("Invalid lobby entry '{field}'"))?;
+                        Ok(LobbyEntry {
+                            name: name.to_string(),
+                            ready: ready == "ready",
+                        })
+                    })
+                    .collect::<anyhow::Result<_>>()?;
+                ServerMsg::Lobby { players, in_round }
+            }
+            "start" => {
+                let you = match val {
+                    "-" => None,
+                    you => Some(you.parse().context("Invalid player number")?),
+                };
+                read_start(reader, you)?
+            }
+            "tick" => {
+                let mut fields = val.split(' ');
+                let apple = decode_point(fields.next().unwrap_or_default())?;
+                let players = fields.map(decode_change).collect::<anyhow::Result<_>>()?;
+                ServerMsg::Tick(Delta { apple, players })
+            }
+            "over" => ServerMsg::Over(decode_state(val)?),
+            "error" => ServerMsg::Error(val.to_string()),
+            _ => bail!("Unknown message '{kind}'"),
+        };
+
+        Ok(Some(msg))
+    }
+}
+
+/// Reads the rest of a `start` message, up to its closing `go`
+fn read_start(reader: &mut impl BufRead, you: Option<usize>) -> anyhow::Result<ServerMsg> {
+    let mut config = ConfigReader::default();
+    let mut players = Vec::new();
+    let mut apple = None;
+    loop {
+        let line = read_line(reader, MAX_SERVER_LINE)?
+            .ok_or_else(|| anyhow!("Connection closed mid-message"))?;
+        let (key, val) = line.split_once(' ').unwrap_or((&line, ""));
+        if config.read(key, val)? {
+            continue;
+        }
+        match key {
+            "player" => {
+                let fields: Vec<&str> = val.split(' ').collect();
+                let [score, apples, death, body] = fields[..] else {
+                    bail!("Invalid player '{val}'");
+                };
+                players.push(PlayerState {
+                    body: body
+                        .split(';')
+                        .map(decode_segment)
+                        .collect::<anyhow::Result<_>>()?,
+                    score: score.parse().context("Invalid score")?,
+                    apples_eaten: apples.parse().context("Invalid apple count")?,
+                    death: decode_death(death)?,
+                });
+            }
+            "apple" => apple = Some(decode_point(val)?),
+            "go" => break,
+            _ => bail!("Unknown start field '{key}'"),
+        }
+    }
+
+    let config = config.finish()?;
+    let snapshot = Snapshot {
+        players,
+        apple: apple.ok_or_else(|| anyhow!("Missing apple"))?,
+    };
+    check_snapshot(&config, &snapshot)?;
+    if let Some(you) = you.filter(|&you| you >= config.players) {
+        bail!("There's no player {you} to play as");
+    }
+
+    Ok(ServerMsg::Start {
+        you,
+        config,
+        snapshot,
+    })
+}
+
+/// Checks that a snapshot fits the game it's said to be from, with a snake
+/// for every player and everything on the board
+fn check_snapshot(config: &SimConfig, snapshot: &Snapshot) -> anyhow::Result<()> {
+    let board = config.board;
+    let on_board = |pos: TermPoint| pos.row < board.height && pos.col < board.width;
+    if snapshot.players.len() != config.players {
+        bail!(
+            "The game has {} players but {} snakes",
+            config.players,
+            snapshot.players.len()
+        );
+    }
+    for state in snapshot.players.iter() {
+        if state.body.is_empty() {
+            bail!("A snake has no body");
+        }
+        if !state.body.iter().all(|seg| on_board(seg.pos)) {
+            bail!("A snake is off the board");
+        }
+    }
+    if !on_board(snapshot.apple) {
+        bail!("The apple is off the board");
+    }
+
+    Ok(())
+}
+
+/// Reads a line without its line ending, or `None` at the end of the stream.
+/// Lines longer than `max_len` are an error rather than read into memory.
+fn read_line(reader: &mut impl BufRead, max_len: usize) -> anyhow::Result<Option<String>> {
+    let mut line = String::new();
+    let read = (&mut *reader)
+        .take(max_len as u64 + 1)
+        .read_line(&mut line)?;
+    if read == 0 {
+        return Ok(None);
+    }
+    if read > max_len && !line.ends_with('\n') {
+        bail!("Line too long");
+    }
+    let len = line.trim_end_matches(['\r', '\n']).len();
+    line.truncate(len);
+
+    Ok(Some(line))
+}
+
+/// Sends messages on a connection from a thread of its own, so one that's
+/// slow to read can't hold up whoever's sending. The connection is shut down
+/// once it falls too far behind or a write fails, and after everything queued
+/// has been sent once the outbox is dropped.
+pub struct Outbox {
+    stream: TcpStream,
+    queue: SyncSender<String>,
+}
+
+impl Outbox {
+    pub fn new(stream: TcpStream) -> anyhow::Result<Self> {
+        let mut writer = stream.try_clone()?;
+        writer.set_write_timeout(Some(WRITE_TIMEOUT))?;
+        let (queue, rx) = sync_channel::<String>(OUTBOX_LEN);
+        thread::spawn(move || {
+            for msg in rx {
+                if send(&mut writer, &msg).is_err() {
+                    break;
+                }
+            }
+            let _ = writer.shutdown(Shutdown::Both);
+        });
+
+        Ok(Outbox { stream, queue })
+    }
+
+    /// Queues a message, returning `false` if the connection has been lost
+    /// or dropped for falling behind
+    pub fn send(&self, msg: &impl Display) -> bool {
+        if self.queue.try_send(msg.to_string()).is_ok() {
+            return true;
+        }
+        let _ = self.stream.shutdown(Shutdown::Both);
+
+        false
+    }
+}
+
+/// Sends a message in one write, so messages from different threads can't
+/// interleave
+pub fn send(writer: &mut impl Write, msg: &impl Display) -> anyhow::Result<()> {
+    let mut buf = String::new();
+    let _ = write!(buf, "{msg}");
+    writer.write_all(buf.as_bytes())?;
+    writer.flush()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::BufReader, net::TcpListener, time::Instant};
+
+    use super::*;
+    use crate::{
+        maps::Map,
+        sim::{Board, DeathCause, Simulation, WallMode},
+    };
+
+    fn round_trip(msg: &ServerMsg) -> ServerMsg {
+        let text = msg.to_string();
+        let mut reader = BufReader::new(text.as_bytes());
+        let read = ServerMsg::read(&mut reader).unwrap().unwrap();
+        assert!(ServerMsg::read(&mut reader).unwrap().is_none());
+
+        read
+    }
+
+    #[test]
+    fn start_message_carries_the_whole_game() {
+        let map = Map::builtins().remove(0);
+        let board = Board::new(map.width + 2, map.height + 2, WallMode::Wrap);
+        let config = SimConfig {
+            seed: 42,
+            progressive: true,
+            map: Some(map.into()),
+            ..SimConfig::new(board, 2)
+        };
+        let mut sim = Simulation::new(config.clone());
+        sim.forfeit(1, DeathCause::Timeout);
+        let msg = ServerMsg::Start {
+            you: Some(1),
+            config: config.clone(),
+            snapshot: sim.snapshot(),
+        };
+        let watching = ServerMsg::Start {
+            you: None,
+            config,
+            snapshot: sim.snapshot(),
+        };
+
+        assert_eq!(round_trip(&msg), msg);
+        assert_eq!(round_trip(&watching), watching);
+    }
+
+    /// Reads `msg` back with its lines changed by `edit`, or left out where
+    /// it returns `None`
+    fn read_edited(
+        msg: &ServerMsg,
+        edit: impl FnMut(&str) -> Option<String>,
+    ) -> anyhow::Result<Option<ServerMsg>> {
+        let text: String = msg
+            .to_string()
+            .lines()
+            .filter_map(edit)
+            .map(|line| line + "\n")
+            .collect();
+        ServerMsg::read(&mut text.as_bytes())
+    }
+
+    #[test]
+    fn start_messages_that_dont_fit_the_game_are_refused() {
+        let config = SimConfig::new(Board::new(10, 6, WallMode::Solid), 2);
+        let start = ServerMsg::Start {
+            you: Some(1),
+            config: config.clone(),
+            snapshot: Simulation::new(config).snapshot(),
+        };
+
+        let mut kept_a_snake = false;
+        let one_snake = read_edited(&start, |line| {
+            let dropped = kept_a_snake && line.starts_with("player ");
+            kept_a_snake |= line.starts_with("player ");
+            (!dropped).then(|| line.to_string())
+        });
+        let no_such_player = read_edited(&start, |line| match line {
+            "start 1" => Some("start 2".to_string()),
+            line => Some(line.to_string()),
+        });
+        let apple_off_the_board = read_edited(&start, |line| match line {
+            line if line.starts_with("apple ") => Some("apple 6,3".to_string()),
+            line => Some(line.to_string()),
+        });
+
+        assert!(one_snake.is_err());
+        assert!(no_such_player.is_err());
+        assert!(apple_off_the_board.is_err());
+        assert!(read_edited(&start, |line| Some(line.to_string())).is_ok());
+    }
+
+    #[test]
+    fn tick_and_lobby_messages_round_trip() {
+        let tick = ServerMsg::Tick(Delta {
+            apple: TermPoint::new(3, 4),
+            players: vec![PlayerDelta {
+                player: 1,
+                head: Some(BodySegment::new(2, 5, crate::snake::Dir::Up)),
+                length: 4,
+                score: 200,
+                apples_eaten: 2,
+                death: Some(Death {
+                    cause: DeathCause::SelfCollision { segment: 3 },
+                    head: TermPoint::new(2, 5),
+                }),
+            }],
+        });
+        let lobby = ServerMsg::Lobby {
+            players: vec![
+                LobbyEntry {
+                    name: "alice".to_string(),
+                    ready: true,
+                },
+                LobbyEntry {
+                    name: "bob".to_string(),
+                    ready: false,
+                },
+            ],
+            in_round: false,
+        };
+
+        assert_eq!(round_trip(&tick), tick);
+        assert_eq!(round_trip(&lobby), lobby);
+    }
+
+    #[test]
+    fn overlong_lines_are_refused() {
+        let name = "x".repeat(MAX_CLIENT_LINE);
+        let hello = format!("hello {PROTOCOL_VERSION} {name}\nready\n");
+        let error = ClientMsg::read(&mut hello.as_bytes()).unwrap_err();
+        assert_eq!(error.to_string(), "Line too long");
+
+        let ready = ClientMsg::Ready(true).to_string();
+        let msg = ClientMsg::read(&mut ready.as_bytes()).unwrap();
+        assert_eq!(msg, Some(ClientMsg::Ready(true)));
+    }
+
+    #[test]
+    fn outbox_drops_a_connection_that_stops_reading() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        // connected, but never read from
+        let (_peer, _) = listener.accept().unwrap();
+        let outbox = Outbox::new(stream).unwrap();
+
+        let msg = "x".repeat(256 * 1024);
+        let start = Instant::now();
+        let queued = (0..4 * OUTBOX_LEN)
+            .take_while(|_| outbox.send(&msg))
+            .count();
+        assert!(queued < 4 * OUTBOX_LEN);
+        assert!(start.elapsed() < WRITE_TIMEOUT);
+        assert!(!outbox.send(&"tick"));
+    }
+}
@@ -0,0 +1,610 @@
+use std::{fmt::Write as _, io::Write, str::FromStr};
+
+use anyhow::bail;
+use console::Term;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    settings::{CellStyle, Color, Colors},
+    sim::{SimConfig, Simulation, Snapshot},
+};
+
+/// How board cells are laid out on the terminal.
+///
+/// Terminal cells are about twice as tall as they are wide, so with one
+/// character per board cell the snake covers ground faster going up and down
+/// than going sideways. The other shapes even that out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CellShape {
+    /// One character per cell
+    #[default]
+    Narrow,
+    /// Two characters side by side per cell
+    Square,
+    /// Two cells stacked in each character using half blocks. Only colors are
+    /// shown, not the snake's direction.
+    HalfBlock,
+}
+
+impl CellShape {
+    pub const ALL: [CellShape; 3] = [CellShape::Narrow, CellShape::Square, CellShape::HalfBlock];
+
+    /// The size of the terminal needed for a board, as (width, height).
+    ///
+    /// The score bar is drawn over the bottom row, so half blocks need a row of
+    /// their own for it whenever the bottom border shares a row with the
+    /// playfield.
+    pub fn screen_size(&self, width: usize, height: usize) -> (usize, usize) {
+        match self {
+            CellShape::Narrow => (width, height),
+            CellShape::Square => (width * 2, height),
+            CellShape::HalfBlock => (width, height / 2 + 1),
+        }
+    }
+
+    /// The largest board that fits in a terminal, as (width, height)
+    pub fn board_size(&self, width: usize, height: usize) -> (usize, usize) {
+        match self {
+            CellShape::Narrow => (width, height),
+            CellShape::Square => (width / 2, height),
+            CellShape::HalfBlock => (width, height.saturating_sub(1) * 2),
+        }
+    }
+
+    /// Where a board cell starts on the screen, as (row, column)
+    pub fn to_screen(&self, row: usize, col: usize) -> (usize, usize) {
+        match self {
+            CellShape::Narrow => (row, col),
+            CellShape::Square => (row, col * 2),
+            CellShape::HalfBlock => (row / 2, col),
+        }
+    }
+
+    /// Lays out a frame of board cells as a frame of terminal characters
+    pub fn layout(&self, board: &Frame) -> Frame {
+        if *self == CellShape::Narrow {
+            return board.clone();
+        }
+        let (width, height) = self.screen_size(board.width, board.height);
+        let mut screen = Frame::new(width, height);
+        match self {
+            CellShape::Narrow => unreachable!("Returned above"),
+            CellShape::Square => {
+                for row in 0..board.height {
+                    for col in 0..board.width {
+                        let cell = board.get(row, col);
+                        let (row, col) = self.to_screen(row, col);
+                        // blocks stretch across both halves, anything else is
+                        // padded out in the same style
+                        let fill = if cell.ch == '█' { '█' } else { ' ' };
+                        screen.set(row, col, cell.ch, cell.style);
+                        screen.set(row, col + 1, fill, cell.style);
+                    }
+                }
+            }
+            CellShape::HalfBlock => {
+                for row in (0..board.height).step_by(2) {
+                    for col in 0..board.width {
+                        let top = block_color(board.get(row, col));
+                        let bottom = (row + 1 < board.height)
+                            .then(|| block_color(board.get(row + 1, col)))
+                            .flatten();
+                        let (ch, style) = match (top, bottom) {
+                            (None, None) => (' ', CellStyle::default()),
+                            (Some(top), None) => ('▀', CellStyle::fg(top)),
+                            (None, Some(bottom)) => ('▄', CellStyle::fg(bottom)),
+                            (Some(top), Some(bottom)) if top == bottom => ('█', CellStyle::fg(top)),
+                            (Some(top), Some(bottom)) => ('▀', CellStyle::new(top, bottom)),
+                        };
+                        let (row, col) = self.to_screen(row, col);
+                        screen.set(row, col, ch, style);
+                    }
+                }
+            }
+        }
+
+        screen
+    }
+}
+
+/// The color a board cell is filled with when drawn as a half block
+fn block_color(cell: Cell) -> Option<Color> {
+    (cell.ch != ' ').then(|| cell.style.fg.unwrap_or(Color::White))
+}
+
+impl std::fmt::Display for CellShape {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CellShape::Narrow => write!(f, "narrow"),
+            CellShape::Square => write!(f, "square"),
+            CellShape::HalfBlock => write!(f, "half-block"),
+        }
+    }
+}
+
+impl FromStr for CellShape {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "narrow" => Ok(CellShape::Narrow),
+            "square" => Ok(CellShape::Square),
+            "half-block" => Ok(CellShape::HalfBlock),
+            _ => bail!("Unknown cell shape '{s}' (expected narrow, square or half-block)"),
+        }
+    }
+}
+
+/// A single character on screen and how it's styled
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cell {
+    pub ch: char,
+    pub style: CellStyle,
+}
+
+impl Default for Cell {
+    fn default() -> Self {
+        Cell {
+            ch: ' ',
+            style: CellStyle::default(),
+        }
+    }
+}
+
+/// Everything drawn on screen for one tick, laid out in terminal rows and columns
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    width: usize,
+    height: usize,
+    cells: Vec<Cell>,
+}
+
+impl Frame {
+    pub fn new(width: usize, height: usize) -> Self {
+        Frame {
+            width,
+            height,
+            cells: vec![Cell::default(); width * height],
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn get(&self, row: usize, col: usize) -> Cell {
+        self.cells[row * self.width + col]
+    }
+
+    /// Sets one cell, ignoring positions outside the frame
+    pub fn set(&mut self, row: usize, col: usize, ch: char, style: CellStyle) {
+        if row < self.height && col < self.width {
+            self.cells[row * self.width + col] = Cell { ch, style };
+        }
+    }
+
+    /// Writes `text` rightwards from the given cell, clipped to the frame
+    pub fn put_str(&mut self, row: usize, col: usize, text: &str, style: CellStyle) {
+        for (i, ch) in text.chars().enumerate() {
+            self.set(row, col + i, ch, style);
+        }
+    }
+
+    /// The frame's characters, one string per row, without any styling
+    pub fn lines(&self) -> Vec<String> {
+        self.cells
+            .chunks(self.width.max(1))
+            .map(|row| row.iter().map(|cell| cell.ch).collect())
+            .collect()
+    }
+
+    /// A copy with every style dropped and anything outside ASCII swapped for `#`
+    pub fn to_plain(&self) -> Frame {
+        let cells = self
+            .cells
+            .iter()
+            .map(|cell| Cell {
+                ch: if cell.ch.is_ascii() { cell.ch } else { '#' },
+                style: CellStyle::default(),
+            })
+            .collect();
+        Frame {
+            width: self.width,
+            height: self.height,
+            cells,
+        }
+    }
+}
+
+impl std::fmt::Display for Frame {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for line in self.lines() {
+            writeln!(f, "{}", line.trim_end())?;
+        }
+        Ok(())
+    }
+}
+
+/// Lays out the border, obstacles, apple and snakes, then the score bar over
+/// the bottom border
+pub fn compose(sim: &Simulation, colors: &Colors, shape: CellShape) -> Frame {
+    compose_snapshot(sim.config(), &sim.snapshot(), colors, shape)
+}
+
+/// Lays out a game from its config and a snapshot of it, as `compose` does,
+/// for when there's no `Simulation` to hand, such as on a network client
+pub fn compose_snapshot(
+    config: &SimConfig,
+    snapshot: &Snapshot,
+    colors: &Colors,
+    shape: CellShape,
+) -> Frame {
+    let board = config.board;
+    let mut frame = Frame::new(board.width, board.height);
+
+    // draw border
+    let border_block = '█';
+    for col in 0..board.width {
+        frame.set(0, col, border_block, colors.border);
+        frame.set(board.height - 1, col, border_block, colors.border);
+    }
+    for row in 1..board.height - 1 {
+        frame.set(row, 0, border_block, colors.border);
+        frame.set(row, board.width - 1, border_block, colors.border);
+    }
+    // draw obstacles
+    for wall in config.map.iter().flat_map(|map| map.walls.iter()) {
+        frame.set(wall.row, wall.col, border_block, colors.border);
+    }
+
+    // draw apple
+    let apple = snapshot.apple;
+    frame.set(apple.row, apple.col, 'O', colors.apple);
+
+    // draw snakes
+    for (i, player) in snapshot.players.iter().enumerate() {
+        let style = if i % 2 == 0 {
+            colors.snake
+        } else {
+            colors.rival
+        };
+        for part in player.body.iter() {
+            frame.set(part.pos.row, part.pos.col, part.glyph(), style);
+        }
+    }
+
+    let mut frame = shape.layout(&frame);
+    // score
+    let apples = snapshot.players.iter().map(|p| p.apples_eaten).sum();
+    let level = config.level(apples);
+    let score_str = match &snapshot.players[..] {
+        [player] => format!("Score: {}  Speed: {level}", player.score),
+        players => {
+            let mut scores = String::new();
+            for (i, player) in players.iter().enumerate() {
+                let _ = write!(scores, "P{}: {}  ", i + 1, player.score);
+            }
+            format!("{scores}Speed: {level}")
+        }
+    };
+    frame.put_str(frame.height - 1, 0, &score_str, colors.score_bar);
+
+    frame
+}
+
+/// The escape sequence switching the terminal to `style`, resetting anything
+/// left over from the previous one
+fn sgr(style: CellStyle) -> String {
+    let mut code = "\x1b[0".to_string();
+    if let Some(fg) = style.fg {
+        let _ = write!(code, ";{}", 30 + fg.ansi_index());
+    }
+    if let Some(bg) = style.bg {
+        let _ = write!(code, ";{}", 40 + bg.ansi_index());
+    }
+    code.push('m');
+
+    code
+}
+
+/// Something the game can be drawn on
+pub trait Renderer {
+    fn draw(&mut self, frame: &Frame) -> anyhow::Result<()>;
+
+    /// Forgets what was last drawn, so the next frame is drawn in full. Call
+    /// this after anything else has drawn over the output, e.g. a menu.
+    fn invalidate(&mut self) {}
+}
+
+/// Picks the terminal backend for the given settings
+pub fn terminal_renderer(term: Term, plain: bool) -> Box<dyn Renderer> {
+    if plain {
+        Box::new(PlainRenderer::new(term))
+    } else {
+        Box::new(AnsiRenderer::new(term))
+    }
+}
+
+/// Draws in color on an ANSI terminal
+pub struct AnsiRenderer {
+    term: Term,
+    frames: FrameBuffer,
+}
+
+impl AnsiRenderer {
+    pub fn new(term: Term) -> Self {
+        AnsiRenderer {
+            term,
+            frames: FrameBuffer::new(),
+        }
+    }
+}
+
+impl Renderer for AnsiRenderer {
+    fn draw(&mut self, frame: &Frame) -> anyhow::Result<()> {
+        self.frames.draw(&mut self.term, frame.clone())
+    }
+
+    fn invalidate(&mut self) {
+        self.frames.invalidate();
+    }
+}
+
+/// Draws with ASCII characters only and no colors, for terminals that can
+/// only position the cursor
+pub struct PlainRenderer {
+    term: Term,
+    frames: FrameBuffer,
+}
+
+impl PlainRenderer {
+    pub fn new(term: Term) -> Self {
+        PlainRenderer {
+            term,
+            frames: FrameBuffer::new(),
+        }
+    }
+}
+
+impl Renderer for PlainRenderer {
+    fn draw(&mut self, frame: &Frame) -> anyhow::Result<()> {
+        self.frames.draw(&mut self.term, frame.to_plain())
+    }
+
+    fn invalidate(&mut self) {
+        self.frames.invalidate();
+    }
+}
+
+/// Keeps the last frame in memory instead of drawing it anywhere, so tests
+/// can compare whole frames against the expected text
+#[derive(Debug, Default)]
+pub struct TextGrid {
+    frame: Option<Frame>,
+    frames_drawn: usize,
+}
+
+impl TextGrid {
+    pub fn new() -> Self {
+        TextGrid::default()
+    }
+
+    /// The last frame drawn, if any
+    pub fn frame(&self) -> Option<&Frame> {
+        self.frame.as_ref()
+    }
+
+    pub fn frames_drawn(&self) -> usize {
+        self.frames_drawn
+    }
+
+    /// The last frame as text, one line per row with trailing spaces removed
+    pub fn snapshot(&self) -> String {
+        self.frame
+            .as_ref()
+            .map(ToString::to_string)
+            .unwrap_or_default()
+    }
+}
+
+impl Renderer for TextGrid {
+    fn draw(&mut self, frame: &Frame) -> anyhow::Result<()> {
+        self.frame = Some(frame.clone());
+        self.frames_drawn += 1;
+
+        Ok(())
+    }
+}
+
+/// The offset that centers `frame` in the terminal, or pins it to the top
+/// left if the terminal is too small
+fn centered(term: &Term, frame: &Frame) -> (usize, usize) {
+    let (ht, wt) = term.size();
+    (
+        (ht as usize).saturating_sub(frame.height) / 2,
+        (wt as usize).saturating_sub(frame.width) / 2,
+    )
+}
+
+/// Draws frames by sending only the cells that changed since the last one.
+///
+/// Each frame goes out in a single write, so the terminal never shows a half
+/// drawn board, and a typical tick costs a few dozen bytes rather than a full
+/// screen's worth.
+#[derive(Debug, Default)]
+pub struct FrameBuffer {
+    prev: Option<Frame>,
+    /// Where the top left of the frame sits on screen, as (row, column)
+    offset: (usize, usize),
+}
+
+impl FrameBuffer {
+    pub fn new() -> Self {
+        FrameBuffer::default()
+    }
+
+    pub fn invalidate(&mut self) {
+        self.prev = None;
+    }
+
+    /// The bytes that turn the frame on screen into `frame`, drawn with its
+    /// top left corner at `offset`
+    pub fn diff(&self, frame: &Frame, offset: (usize, usize)) -> Vec<u8> {
+        let prev = self.prev.as_ref().filter(|prev| {
+            prev.width == frame.width && prev.height == frame.height && self.offset == offset
+        });
+        let mut out = String::new();
+        if prev.is_none() {
+            out.push_str("\x1b[0m\x1b[2J");
+        }
+
+        let mut cursor = None;
+        // the terminal's style is always reset between frames
+        let mut curr_style = CellStyle::default();
+        for row in 0..frame.height {
+            for col in 0..frame.width {
+                let cell = frame.get(row, col);
+                let unchanged = match prev {
+                    Some(prev) => prev.get(row, col) == cell,
+                    // the screen was just cleared
+                    None => cell == Cell::default(),
+                };
+                if unchanged {
+                    continue;
+                }
+                if cursor != Some((row, col)) {
+                    let _ = write!(out, "\x1b[{};{}H", offset.0 + row + 1, offset.1 + col + 1);
+                }
+                if curr_style != cell.style {
+                    out.push_str(&sgr(cell.style));
+                    curr_style = cell.style;
+                }
+                out.push(cell.ch);
+                cursor = Some((row, col + 1));
+            }
+        }
+        if curr_style != CellStyle::default() {
+            out.push_str("\x1b[0m");
+        }
+
+        out.into_bytes()
+    }
+
+    /// Draws `frame` centered in the terminal
+    pub fn draw(&mut self, term: &mut Term, frame: Frame) -> anyhow::Result<()> {
+        let offset = centered(term, &frame);
+        let out = self.diff(&frame, offset);
+        if !out.is_empty() {
+            term.write_all(&out)?;
+            term.flush()?;
+        }
+        self.prev = Some(frame);
+        self.offset = offset;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        input::UserInput,
+        sim::{Board, SimConfig, WallMode},
+    };
+
+    use super::*;
+
+    /// A snake of three heading right along the top row of a small board
+    fn small_game() -> Simulation {
+        Simulation::new(SimConfig {
+            seed: 1,
+            ..SimConfig::new(Board::new(20, 5, WallMode::Solid), 1)
+        })
+    }
+
+    fn drawn(shape: CellShape) -> String {
+        let mut grid = TextGrid::new();
+        let frame = compose(&small_game(), &Colors::default(), shape);
+        grid.draw(&frame).unwrap();
+        assert_eq!(grid.frames_drawn(), 1);
+        grid.snapshot()
+    }
+
+    #[test]
+    fn narrow_cells_draw_one_character_each() {
+        let expected = "\
+            ████████████████████\n\
+            █>>>               █\n\
+            █                  █\n\
+            █                O █\n\
+            Score: 0  Speed: 1██\n";
+        assert_eq!(drawn(CellShape::Narrow), expected);
+    }
+
+    #[test]
+    fn square_cells_draw_two_characters_each() {
+        let expected = "\
+            ████████████████████████████████████████\n\
+            ██> > >                               ██\n\
+            ██                                    ██\n\
+            ██                                O   ██\n\
+            Score: 0  Speed: 1██████████████████████\n";
+        assert_eq!(drawn(CellShape::Square), expected);
+    }
+
+    #[test]
+    fn half_blocks_stack_two_rows_in_each_character() {
+        let expected = "\
+            █▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀▀█\n\
+            █                ▄ █\n\
+            Score: 0  Speed: 1▀▀\n";
+        assert_eq!(drawn(CellShape::HalfBlock), expected);
+    }
+
+    #[test]
+    fn a_snapshot_kept_up_to_date_draws_like_the_game() {
+        let mut sim = Simulation::new(SimConfig {
+            progressive: true,
+            ..SimConfig::new(Board::new(20, 5, WallMode::Solid), 2)
+        });
+        let mut snapshot = sim.snapshot();
+        let colors = Colors::default();
+        for _ in 0..4 {
+            let before = sim.snapshot();
+            sim.update_state(UserInput::Unknown);
+            snapshot.apply(&before.delta(&sim.snapshot()));
+
+            assert_eq!(
+                compose_snapshot(sim.config(), &snapshot, &colors, CellShape::Narrow),
+                compose(&sim, &colors, CellShape::Narrow)
+            );
+        }
+    }
+
+    #[test]
+    fn a_tick_redraws_far_less_than_the_first_frame() {
+        let mut sim = Simulation::new(SimConfig {
+            board: Board::new(40, 20, WallMode::Solid),
+            ..small_game().config().clone()
+        });
+        let colors = Colors::default();
+        let first = compose(&sim, &colors, CellShape::Narrow);
+        sim.update_state(UserInput::Unknown);
+        let second = compose(&sim, &colors, CellShape::Narrow);
+
+        let mut frames = FrameBuffer::new();
+        let full = frames.diff(&first, (0, 0));
+        frames.prev = Some(first);
+        let tick = frames.diff(&second, (0, 0));
+        assert!(tick.len() * 10 < full.len());
+        // the new head, and a blank where the tail was
+        let text = String::from_utf8(tick).unwrap();
+        assert!(text.contains("\x1b[2;5H"));
+        assert!(text.contains("\x1b[2;2H"));
+    }
+}
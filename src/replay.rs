@@ -0,0 +1,601 @@
+use std::{
+    fs,
+    io::Write,
+    path::Path,
+    str::FromStr,
+    sync::{
+        mpsc::{Receiver, RecvTimeoutError},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, bail, Context};
+use console::{Key, Term};
+
+use crate::{
+    game::{show_outcome, wait_for_room},
+    input::{spawn_input_thread, UserInput},
+    maps::Map,
+    render::{compose, terminal_renderer},
+    settings::{GameSettings, MAX_TICK_MS},
+    sim::{Board, Death, DeathCause, GameState, SimConfig, Simulation, WallMode},
+    snake::TermPoint,
+};
+
+/// Bumped whenever the replay file format changes incompatibly
+pub const REPLAY_VERSION: u32 = 1;
+const REPLAY_HEADER: &str = "rusty_snake replay";
+
+/// Everything needed to reproduce a game: the simulation's starting
+/// parameters plus the input fed to it on every tick
+#[derive(Debug, Clone, PartialEq)]
+pub struct Replay {
+    pub config: SimConfig,
+    /// Length of a tick at speed level 1 when the game was played, in
+    /// milliseconds
+    pub tick_ms: f64,
+    /// One input per player for every tick, in player order
+    pub inputs: Vec<UserInput>,
+    /// How the game ended, if the snake died
+    pub death: Option<Death>,
+}
+
+impl Replay {
+    pub fn new(config: SimConfig, tick_ms: f64) -> Self {
+        Replay {
+            config,
+            tick_ms,
+            inputs: Vec::new(),
+            death: None,
+        }
+    }
+
+    pub fn push(&mut self, input: UserInput) {
+        self.inputs.push(input);
+    }
+
+    /// The inputs for each tick, one per player
+    pub fn ticks(&self) -> impl Iterator<Item = &[UserInput]> {
+        self.inputs.chunks(self.config.players.max(1))
+    }
+
+    /// A warning if a replayed game didn't end the way the recording says it
+    /// did, which means the rules have changed since it was made. Only deaths
+    /// are recorded, so other endings can't be checked.
+    pub fn mismatch(&self, state: GameState) -> Option<String> {
+        let recorded = self.death?;
+        match state {
+            GameState::Over(death) if death == recorded => None,
+            _ => Some(format!("The recording ended differently: {recorded}")),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        fs::write(path, self.to_string())
+            .with_context(|| format!("Failed to write replay to {}", path.display()))
+    }
+
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read replay from {}", path.display()))?;
+        contents
+            .parse()
+            .with_context(|| format!("Invalid replay file {}", path.display()))
+    }
+}
+
+pub fn encode_input(input: UserInput) -> char {
+    match input {
+        UserInput::Unknown => '?',
+        UserInput::Pause => 'P',
+        UserInput::Up => 'U',
+        UserInput::Down => 'D',
+        UserInput::Left => 'L',
+        UserInput::Right => 'R',
+    }
+}
+
+pub fn decode_input(c: char) -> anyhow::Result<UserInput> {
+    Ok(match c {
+        '?' => UserInput::Unknown,
+        'P' => UserInput::Pause,
+        'U' => UserInput::Up,
+        'D' => UserInput::Down,
+        'L' => UserInput::Left,
+        'R' => UserInput::Right,
+        _ => bail!("Unknown input '{c}'"),
+    })
+}
+
+pub fn encode_cause(cause: DeathCause) -> String {
+    match cause {
+        DeathCause::Wall => "wall".to_string(),
+        DeathCause::SelfCollision { segment } => format!("self:{segment}"),
+        DeathCause::Obstacle => "obstacle".to_string(),
+        DeathCause::Opponent { segment } => format!("opponent:{segment}"),
+        DeathCause::Timeout => "timeout".to_string(),
+    }
+}
+
+pub fn decode_cause(s: &str) -> anyhow::Result<DeathCause> {
+    let (kind, segment) = s.split_once(':').unwrap_or((s, ""));
+    let segment = || segment.parse().context("Invalid segment index");
+    Ok(match kind {
+        "wall" => DeathCause::Wall,
+        "self" => DeathCause::SelfCollision {
+            segment: segment()?,
+        },
+        "obstacle" => DeathCause::Obstacle,
+        "opponent" => DeathCause::Opponent {
+            segment: segment()?,
+        },
+        "timeout" => DeathCause::Timeout,
+        _ => bail!("Unknown cause of death '{s}'"),
+    })
+}
+
+/// Parses a `death` line, written as `<cause> <row> <column>`
+fn decode_death(s: &str) -> anyhow::Result<Death> {
+    let mut parts = s.split(' ');
+    let (Some(cause), Some(row), Some(col), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        bail!("Invalid death '{s}'");
+    };
+    Ok(Death {
+        cause: decode_cause(cause)?,
+        head: TermPoint::new(
+            row.parse().context("Invalid death row")?,
+            col.parse().context("Invalid death column")?,
+        ),
+    })
+}
+
+/// Writes a game's starting parameters as `<field> <value>` lines, the way
+/// they start a replay file
+pub fn write_config(f: &mut impl std::fmt::Write, config: &SimConfig) -> std::fmt::Result {
+    writeln!(f, "seed {}", config.seed)?;
+    writeln!(f, "board {} {}", config.board.width, config.board.height)?;
+    writeln!(f, "walls {}", config.board.wall_mode)?;
+    writeln!(f, "length {}", config.starting_length)?;
+    writeln!(f, "progressive {}", config.progressive)?;
+    if config.players != 1 {
+        writeln!(f, "players {}", config.players)?;
+    }
+    if let Some(map) = &config.map {
+        writeln!(f, "map_name {}", map.name)?;
+        for row in map.rows.iter() {
+            writeln!(f, "map {row}")?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Collects the lines written by `write_config` back into a `SimConfig`
+#[derive(Debug, Default)]
+pub struct ConfigReader {
+    seed: Option<u64>,
+    size: Option<(usize, usize)>,
+    wall_mode: Option<WallMode>,
+    starting_length: Option<usize>,
+    progressive: Option<bool>,
+    players: Option<usize>,
+    map_name: String,
+    map_rows: Vec<String>,
+}
+
+impl ConfigReader {
+    /// Takes in the field of one line, returning whether it was part of the config
+    pub fn read(&mut self, key: &str, val: &str) -> anyhow::Result<bool> {
+        match key {
+            "seed" => self.seed = Some(val.parse().context("Invalid seed")?),
+            "board" => {
+                let (w, h) = val
+                    .split_once(' ')
+                    .ok_or_else(|| anyhow!("Invalid board size"))?;
+                let width = w.parse().context("Invalid board width")?;
+                let height = h.parse().context("Invalid board height")?;
+                self.size = Some((width, height));
+            }
+            "walls" => self.wall_mode = Some(val.parse()?),
+            "length" => self.starting_length = Some(val.parse().context("Invalid length")?),
+            "progressive" => {
+                self.progressive = Some(val.parse().context("Invalid progressive flag")?);
+            }
+            "players" => self.players = Some(val.parse().context("Invalid player count")?),
+            "map_name" => self.map_name = val.to_string(),
+            "map" => self.map_rows.push(val.to_string()),
+            _ => return Ok(false),
+        }
+
+        Ok(true)
+    }
+
+    pub fn finish(self) -> anyhow::Result<SimConfig> {
+        let (width, height) = self.size.ok_or_else(|| anyhow!("Missing board size"))?;
+        let wall_mode = self.wall_mode.ok_or_else(|| anyhow!("Missing wall mode"))?;
+        // the border takes up a row and column on each side
+        if width < 4 || height < 4 {
+            bail!("A {width}x{height} board has no room for a 2x2 playfield");
+        }
+        let players = self.players.unwrap_or(1);
+        if players == 0 {
+            bail!("A game needs at least one player");
+        }
+        if players > height - 2 {
+            bail!(
+                "A {width}x{height} board only has room for {} players, not {players}",
+                height - 2
+            );
+        }
+        let map = if self.map_rows.is_empty() {
+            None
+        } else {
+            let map = Map::from_rows(self.map_name, self.map_rows).context("Invalid map")?;
+            if (map.width + 2, map.height + 2) != (width, height) {
+                bail!("Map size doesn't match the board size");
+            }
+            Some(Arc::new(map))
+        };
+
+        let starting_length = self
+            .starting_length
+            .ok_or_else(|| anyhow!("Missing length"))?;
+        if starting_length == 0 {
+            bail!("A snake needs a length of at least 1");
+        }
+
+        Ok(SimConfig {
+            board: Board::new(width, height, wall_mode),
+            starting_length,
+            seed: self.seed.ok_or_else(|| anyhow!("Missing seed"))?,
+            progressive: self
+                .progressive
+                .ok_or_else(|| anyhow!("Missing progressive flag"))?,
+            map,
+            players,
+        })
+    }
+}
+
+impl std::fmt::Display for Replay {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{REPLAY_HEADER} v{REPLAY_VERSION}")?;
+        write_config(f, &self.config)?;
+        writeln!(f, "tick_ms {}", self.tick_ms)?;
+        let inputs: String = self.inputs.iter().map(|i| encode_input(*i)).collect();
+        writeln!(f, "inputs {inputs}")?;
+        if let Some(death) = &self.death {
+            let Death { cause, head } = death;
+            writeln!(
+                f,
+                "death {} {} {}",
+                encode_cause(*cause),
+                head.row,
+                head.col
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+impl FromStr for Replay {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut lines = s.lines();
+
+        let header = lines.next().ok_or_else(|| anyhow!("Empty replay"))?;
+        let version = header
+            .strip_prefix(REPLAY_HEADER)
+            .and_then(|rest| rest.trim().strip_prefix('v'))
+            .ok_or_else(|| anyhow!("Missing replay header"))?;
+        let version: u32 = version.parse().context("Invalid replay version")?;
+        if version != REPLAY_VERSION {
+            bail!("Unsupported replay version {version} (expected {REPLAY_VERSION})");
+        }
+
+        let mut config = ConfigReader::default();
+        let mut tick_ms = None;
+        let mut inputs = None;
+        let mut death = None;
+        for line in lines {
+            let (key, val) = line.split_once(' ').unwrap_or((line, ""));
+            if config.read(key, val)? {
+                continue;
+            }
+            match key {
+                "tick_ms" => {
+                    let ms: f64 = val.parse().context("Invalid tick length")?;
+                    if !ms.is_finite() || ms <= 0.0 || ms > MAX_TICK_MS {
+                        bail!("Invalid tick length {ms}");
+                    }
+                    tick_ms = Some(ms);
+                }
+                "inputs" => {
+                    inputs = Some(val.chars().map(decode_input).collect::<Result<_, _>>()?);
+                }
+                "death" => death = Some(decode_death(val)?),
+                "" => {}
+                _ => bail!("Unknown replay field '{key}'"),
+            }
+        }
+
+        Ok(Replay {
+            config: config.finish()?,
+            tick_ms: tick_ms.ok_or_else(|| anyhow!("Missing tick length"))?,
+            inputs: inputs.ok_or_else(|| anyhow!("Missing inputs"))?,
+            death,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlaybackSpeed {
+    Normal,
+    Fast,
+    /// Advance one tick per key press
+    Step,
+}
+
+impl FromStr for PlaybackSpeed {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "normal" => Ok(Self::Normal),
+            "fast" => Ok(Self::Fast),
+            "step" => Ok(Self::Step),
+            _ => bail!("Unknown playback speed '{s}' (expected normal, fast or step)"),
+        }
+    }
+}
+
+fn is_quit_key(key: &Key) -> bool {
+    matches!(key, Key::Escape | Key::Char('q'))
+}
+
+/// Blocks until the next tick should run, returning `false` if the viewer quit
+fn wait_for_tick(input_rcv: &Receiver<Key>, speed: PlaybackSpeed, tick_secs: f64) -> bool {
+    let tick = match speed {
+        PlaybackSpeed::Normal => Duration::from_secs_f64(tick_secs),
+        PlaybackSpeed::Fast => Duration::from_secs_f64(tick_secs / 4.0),
+        PlaybackSpeed::Step => {
+            return match input_rcv.recv() {
+                Ok(key) => !is_quit_key(&key),
+                Err(_) => false,
+            };
+        }
+    };
+
+    let start = Instant::now();
+    while let Some(remaining) = tick.checked_sub(start.elapsed()) {
+        match input_rcv.recv_timeout(remaining) {
+            Ok(key) if is_quit_key(&key) => return false,
+            Ok(_) => {}
+            Err(RecvTimeoutError::Timeout) => break,
+            Err(RecvTimeoutError::Disconnected) => return false,
+        }
+    }
+
+    true
+}
+
+/// Plays back a recorded game through the normal renderer
+pub fn play_replay(
+    mut term: Term,
+    replay: &Replay,
+    speed: PlaybackSpeed,
+    settings: &GameSettings,
+) -> anyhow::Result<()> {
+    let board = replay.config.board;
+    let shape = settings.cell_shape;
+    let (ht, wt) = term.size();
+    let (need_w, need_h) = shape.screen_size(board.width, board.height);
+    if (wt as usize) < need_w || (ht as usize) < need_h {
+        bail!("Replay needs a {need_w}x{need_h} terminal, but this one is only {wt}x{ht}");
+    }
+
+    // normal speed is the speed the game was played at
+    let settings = &GameSettings {
+        tick_ms: Some(replay.tick_ms),
+        ..settings.clone()
+    };
+    let rx = spawn_input_thread(term.clone());
+    let mut sim = Simulation::new(replay.config.clone());
+    let mut renderer = terminal_renderer(term.clone(), settings.plain);
+
+    let mut term_size = term.size();
+    for inputs in replay.ticks() {
+        if term.size() != term_size {
+            if !wait_for_room(&term, &rx, board, shape, is_quit_key)? {
+                return Ok(());
+            }
+            term_size = term.size();
+            renderer.invalidate();
+        }
+        renderer.draw(&compose(&sim, &settings.colors, shape))?;
+        if !wait_for_tick(&rx, speed, settings.tick_secs(sim.level())) {
+            return Ok(());
+        }
+        let state = sim.update(inputs);
+        if state != GameState::Continue {
+            show_outcome(&mut term, &sim, state)?;
+            if let Some(warning) = replay.mismatch(state) {
+                term.write_all(format!("  {warning}").as_bytes())?;
+            }
+            // keep the final frame up until the viewer is done with it
+            rx.recv()?;
+            return Ok(());
+        }
+    }
+
+    renderer.draw(&compose(&sim, &settings.colors, shape))?;
+    term.write_all("End of replay".as_bytes())?;
+    if let Some(warning) = replay.mismatch(GameState::Continue) {
+        term.write_all(format!("  {warning}").as_bytes())?;
+    }
+    rx.recv()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Plays a replay through a fresh simulation, returning how it ended
+    fn replay_state(replay: &Replay) -> (Simulation, GameState) {
+        let mut sim = Simulation::new(replay.config.clone());
+        for inputs in replay.ticks() {
+            let state = sim.update(inputs);
+            if state != GameState::Continue {
+                return (sim, state);
+            }
+        }
+        (sim, GameState::Continue)
+    }
+
+    #[test]
+    fn replays_read_back_the_way_they_were_written() {
+        let map = Map::from_rows("Tiny".to_string(), vec!["#...".into(), "..>.".into()]).unwrap();
+        let mut replay = Replay::new(
+            SimConfig {
+                starting_length: 2,
+                seed: 99,
+                progressive: true,
+                map: Some(Arc::new(map)),
+                ..SimConfig::new(Board::new(6, 4, WallMode::Wrap), 2)
+            },
+            62.5,
+        );
+        for input in [
+            UserInput::Up,
+            UserInput::Unknown,
+            UserInput::Left,
+            UserInput::Down,
+        ] {
+            replay.push(input);
+        }
+        replay.death = Some(Death {
+            cause: DeathCause::Opponent { segment: 1 },
+            head: TermPoint::new(2, 3),
+        });
+        let text = replay.to_string();
+
+        assert!(text.contains("players 2\n"));
+        assert!(text.contains("map #...\nmap ..>.\n"));
+        assert!(text.contains("inputs U?LD\n"));
+        assert!(text.contains("death opponent:1 2 3\n"));
+        assert_eq!(text.parse::<Replay>().unwrap(), replay);
+    }
+
+    /// Reads a small replay, with `fields` added after the ones it has
+    fn parse_with(fields: &str) -> anyhow::Result<Replay> {
+        format!(
+            "rusty_snake replay v{REPLAY_VERSION}\nseed 1\nboard 6 4\nwalls solid\nlength 2\n\
+             progressive false\ntick_ms 100\ninputs R\n{fields}"
+        )
+        .parse()
+    }
+
+    #[test]
+    fn boards_without_a_2x2_playfield_are_refused() {
+        assert!(parse_with("").is_ok());
+        for board in ["board 0 0", "board 3 3", "board 3 6", "board 6 3"] {
+            assert!(parse_with(board).is_err(), "{board}");
+        }
+    }
+
+    #[test]
+    fn games_need_between_one_player_and_one_per_row() {
+        assert!(parse_with("players 2").is_ok());
+        assert!(parse_with("players 0").is_err());
+        assert!(parse_with("players 3").is_err());
+        assert!(parse_with("board 4 3\nplayers 2").is_err());
+        assert!(parse_with("board 3 3\nplayers 3").is_err());
+    }
+
+    #[test]
+    fn snakes_need_a_length() {
+        assert!(parse_with("length 0").is_err());
+    }
+
+    #[test]
+    fn maps_must_fit_the_board() {
+        let map = "map_name Tiny\nmap #...\nmap ..>.";
+        assert!(parse_with(map).is_ok());
+        assert!(parse_with(&format!("{map}\nboard 8 4")).is_err());
+    }
+
+    #[test]
+    fn tick_lengths_must_be_positive_and_not_absurdly_long() {
+        assert!(parse_with("tick_ms 62.5").is_ok());
+        for tick in [
+            "tick_ms 0",
+            "tick_ms -5",
+            "tick_ms NaN",
+            "tick_ms inf",
+            "tick_ms 1e300",
+        ] {
+            assert!(parse_with(tick).is_err(), "{tick}");
+        }
+    }
+
+    #[test]
+    fn other_versions_are_refused() {
+        let current = parse_with("").unwrap().to_string();
+        for version in [0, REPLAY_VERSION + 1] {
+            let other = current.replacen(&format!("v{REPLAY_VERSION}"), &format!("v{version}"), 1);
+            assert!(other.parse::<Replay>().is_err(), "v{version}");
+        }
+    }
+
+    #[test]
+    fn required_fields_must_be_there() {
+        let current = parse_with("").unwrap().to_string();
+        for field in [
+            "seed",
+            "board",
+            "walls",
+            "length",
+            "progressive",
+            "tick_ms",
+            "inputs",
+        ] {
+            let missing: String = current
+                .lines()
+                .filter(|line| !line.starts_with(&format!("{field} ")))
+                .map(|line| format!("{line}\n"))
+                .collect();
+            assert!(missing.parse::<Replay>().is_err(), "{field}");
+        }
+    }
+
+    #[test]
+    fn recorded_game_still_ends_the_same_way() {
+        let replay: Replay = include_str!("../replays/wall.replay").parse().unwrap();
+        let (sim, state) = replay_state(&replay);
+
+        let death = Death {
+            cause: DeathCause::Wall,
+            head: TermPoint::new(3, 11),
+        };
+        assert_eq!(state, GameState::Over(death));
+        assert_eq!(replay.mismatch(state), None);
+        assert_eq!(sim.snake().body.len(), 3);
+    }
+
+    #[test]
+    fn a_different_ending_is_reported() {
+        let mut replay: Replay = include_str!("../replays/wall.replay").parse().unwrap();
+        replay.inputs.pop();
+        let (_, state) = replay_state(&replay);
+
+        assert_eq!(state, GameState::Continue);
+        assert!(replay.mismatch(state).is_some());
+    }
+}
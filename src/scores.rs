@@ -0,0 +1,294 @@
+use std::{
+    collections::BTreeMap,
+    fs::{self, File, OpenOptions},
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+use crate::sim::{SimConfig, WallMode};
+
+/// How many scores are kept for each table
+pub const MAX_ENTRIES: usize = 10;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScoreEntry {
+    pub name: String,
+    pub score: usize,
+    /// Seconds since the Unix epoch when the score was set
+    pub timestamp: u64,
+}
+
+impl ScoreEntry {
+    pub fn new(name: String, score: usize) -> Self {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        ScoreEntry {
+            name,
+            score,
+            timestamp,
+        }
+    }
+}
+
+/// The name of the table scores for a given game mode and game are kept in,
+/// e.g. `Classic 40x20`, or `Classic 40x20 wrap` when the walls wrap around.
+/// Progressive games score more per apple as they speed up, so they get
+/// tables of their own, e.g. `Classic 40x20 progressive`.
+pub fn table_name(mode: &str, config: &SimConfig) -> String {
+    let board = config.board;
+    let mut name = format!("{mode} {}x{}", board.width - 2, board.height - 2);
+    if board.wall_mode == WallMode::Wrap {
+        name.push_str(" wrap");
+    }
+    if config.progressive {
+        name.push_str(" progressive");
+    }
+
+    name
+}
+
+/// Top scores, kept separately for each game mode and board size
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HighScores {
+    #[serde(default)]
+    tables: BTreeMap<String, Vec<ScoreEntry>>,
+}
+
+impl HighScores {
+    /// The default score file location, e.g. `~/.local/share/rusty_snake/scores.toml`
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::data_dir().map(|dir| dir.join("rusty_snake").join("scores.toml"))
+    }
+
+    /// Loads the scores at `path`. A missing file is treated as empty, and a
+    /// corrupted one is moved aside to `<path>.corrupt` so it can't block new
+    /// scores from being saved.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let parsed = match fs::read_to_string(path) {
+            Ok(contents) => toml::from_str::<Self>(&contents).ok(),
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) if e.kind() == ErrorKind::InvalidData => None,
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        };
+        match parsed {
+            Some(mut scores) => {
+                for table in scores.tables.values_mut() {
+                    sort_table(table);
+                }
+                Ok(scores)
+            }
+            None => {
+                let _ = fs::rename(path, path.with_extension("toml.corrupt"));
+                Ok(Self::default())
+            }
+        }
+    }
+
+    pub fn table_names(&self) -> impl Iterator<Item = &String> {
+        self.tables.keys()
+    }
+
+    pub fn table(&self, name: &str) -> &[ScoreEntry] {
+        self.tables.get(name).map(Vec::as_slice).unwrap_or(&[])
+    }
+
+    /// Whether `score` would make it into the named table
+    pub fn qualifies(&self, name: &str, score: usize) -> bool {
+        let table = self.table(name);
+        score > 0 && (table.len() < MAX_ENTRIES || table.iter().any(|e| score > e.score))
+    }
+
+    fn insert(&mut self, name: &str, entry: ScoreEntry) {
+        let table = self.tables.entry(name.to_string()).or_default();
+        table.push(entry);
+        sort_table(table);
+    }
+
+    /// Adds `entry` to the named table and saves the result to `path` if given.
+    ///
+    /// The file is re-read under a lock before writing so scores saved by
+    /// other running games in the meantime aren't lost, and is replaced
+    /// atomically so readers never see a partial write.
+    pub fn record(
+        &mut self,
+        path: Option<&Path>,
+        name: &str,
+        entry: ScoreEntry,
+    ) -> anyhow::Result<()> {
+        let Some(path) = path else {
+            self.insert(name, entry);
+            return Ok(());
+        };
+
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create {}", dir.display()))?;
+        }
+        let _lock = lock_file(&path.with_extension("toml.lock"))?;
+        *self = Self::load(path)?;
+        self.insert(name, entry);
+
+        let tmp_path = path.with_extension("toml.tmp");
+        fs::write(&tmp_path, toml::to_string(self)?)
+            .with_context(|| format!("Failed to write {}", tmp_path.display()))?;
+        fs::rename(&tmp_path, path)
+            .with_context(|| format!("Failed to replace {}", path.display()))?;
+
+        Ok(())
+    }
+}
+
+/// Best score first, with ties going to whoever got there first
+fn sort_table(table: &mut Vec<ScoreEntry>) {
+    table.sort_by(|a, b| b.score.cmp(&a.score).then(a.timestamp.cmp(&b.timestamp)));
+    table.truncate(MAX_ENTRIES);
+}
+
+/// Takes an exclusive lock on the file at `path`, creating it if need be,
+/// and holds it until the returned file is dropped.
+///
+/// The lock is held by the operating system, so it's let go even if the
+/// process dies, and the file is never removed since another process could
+/// then lock a fresh file at the same path while the old one is still held.
+fn lock_file(path: &Path) -> anyhow::Result<File> {
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path)
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    file.lock()
+        .with_context(|| format!("Failed to lock {}", path.display()))?;
+
+    Ok(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use crate::{sim::Board, test_util::TempDir};
+
+    use super::*;
+
+    fn entry(name: &str, score: usize, timestamp: u64) -> ScoreEntry {
+        ScoreEntry {
+            name: name.to_string(),
+            score,
+            timestamp,
+        }
+    }
+
+    #[test]
+    fn tables_are_split_by_walls_and_progressive_speed() {
+        let config = SimConfig::new(Board::new(42, 22, WallMode::Solid), 1);
+        let wrap_progressive = SimConfig {
+            board: Board::new(42, 22, WallMode::Wrap),
+            progressive: true,
+            ..config.clone()
+        };
+
+        assert_eq!(table_name("Classic", &config), "Classic 40x20");
+        assert_eq!(
+            table_name("Classic", &wrap_progressive),
+            "Classic 40x20 wrap progressive"
+        );
+    }
+
+    #[test]
+    fn corrupt_file_is_moved_aside() {
+        let dir = TempDir::new("corrupt");
+        let path = dir.join("scores.toml");
+        fs::write(&path, "tables = 'not a table'").unwrap();
+
+        assert_eq!(HighScores::load(&path).unwrap(), HighScores::default());
+        assert!(!path.exists());
+        let corrupt = fs::read_to_string(path.with_extension("toml.corrupt")).unwrap();
+        assert_eq!(corrupt, "tables = 'not a table'");
+    }
+
+    #[test]
+    fn tables_keep_the_best_scores_first() {
+        let mut scores = HighScores::default();
+        for i in 0..MAX_ENTRIES + 2 {
+            scores.insert("Classic", entry("late", i * 10, 2));
+        }
+        scores.insert("Classic", entry("early", 50, 1));
+
+        let table = scores.table("Classic");
+        assert_eq!(table.len(), MAX_ENTRIES);
+        assert_eq!(table[0].score, (MAX_ENTRIES + 1) * 10);
+        assert!(table.windows(2).all(|pair| pair[0].score >= pair[1].score));
+        let tied: Vec<&str> = table
+            .iter()
+            .filter(|e| e.score == 50)
+            .map(|e| e.name.as_str())
+            .collect();
+        assert_eq!(tied, ["early", "late"]);
+        assert!(!scores.qualifies("Classic", 20));
+        assert!(scores.qualifies("Other", 20));
+    }
+
+    #[test]
+    fn recording_keeps_scores_saved_by_other_games() {
+        let dir = TempDir::new("merge");
+        let path = dir.join("scores.toml");
+        let mut first = HighScores::load(&path).unwrap();
+        let mut second = HighScores::load(&path).unwrap();
+        first
+            .record(Some(&path), "Classic", entry("first", 100, 1))
+            .unwrap();
+        second
+            .record(Some(&path), "Classic", entry("second", 200, 2))
+            .unwrap();
+
+        let names: Vec<&str> = second
+            .table("Classic")
+            .iter()
+            .map(|e| e.name.as_str())
+            .collect();
+        assert_eq!(names, ["second", "first"]);
+        assert_eq!(HighScores::load(&path).unwrap(), second);
+    }
+
+    #[test]
+    fn concurrent_records_are_all_kept() {
+        let dir = TempDir::new("concurrent");
+        let path = dir.join("scores.toml");
+        let games: Vec<_> = (0..MAX_ENTRIES)
+            .map(|i| {
+                let path = path.clone();
+                thread::spawn(move || {
+                    let mut scores = HighScores::default();
+                    let entry = entry(&format!("player{i}"), 100 + i, i as u64);
+                    scores.record(Some(&path), "Classic", entry).unwrap();
+                })
+            })
+            .collect();
+        for game in games {
+            game.join().unwrap();
+        }
+
+        let scores = HighScores::load(&path).unwrap();
+        assert_eq!(scores.table("Classic").len(), MAX_ENTRIES);
+    }
+
+    #[test]
+    fn a_lock_file_left_behind_does_not_block_recording() {
+        let dir = TempDir::new("leftover_lock");
+        let path = dir.join("scores.toml");
+        fs::write(path.with_extension("toml.lock"), "").unwrap();
+        let mut scores = HighScores::default();
+        scores
+            .record(Some(&path), "Classic", entry("alice", 100, 1))
+            .unwrap();
+
+        assert_eq!(HighScores::load(&path).unwrap(), scores);
+    }
+}
@@ -0,0 +1,686 @@
+use std::{
+    collections::BTreeMap,
+    io::BufReader,
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+    ops::RangeInclusive,
+    sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender},
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context};
+
+use crate::{
+    input::{InputQueue, UserInput},
+    net::{ClientMsg, LobbyEntry, Outbox, ServerMsg, HANDSHAKE_TIMEOUT, PROTOCOL_VERSION},
+    settings::{BoardSize, GameSettings},
+    sim::{Board, DeathCause, GameState, SimConfig, Simulation, Snapshot},
+};
+
+/// The most players a round can have, so every snake gets a row to start on
+pub const MAX_PLAYERS: usize = 8;
+/// Board used when the settings leave it to fill the terminal, which a server
+/// can't do for terminals it doesn't know
+pub const DEFAULT_BOARD: BoardSize = BoardSize {
+    width: 40,
+    height: 20,
+};
+/// The largest board a server plays on, small enough that a snake filling it
+/// still fits in the one line of a `Start` message
+pub const MAX_BOARD: BoardSize = BoardSize {
+    width: 256,
+    height: 256,
+};
+/// How often the lobby checks for news when there's no round to tick
+const LOBBY_POLL: Duration = Duration::from_millis(100);
+
+type ClientId = u64;
+
+/// Something that happened on one of the connections, passed from its
+/// thread to the server's
+enum Event {
+    Connected(ClientId, TcpStream),
+    Message(ClientId, ClientMsg),
+    Disconnected(ClientId),
+}
+
+struct Client {
+    outbox: Outbox,
+    /// Set once the client has said hello and joined the lobby
+    name: Option<String>,
+    /// Whether the client is only here to watch
+    watching: bool,
+    ready: bool,
+    /// Their player number in the round being played, if they're in it
+    player: Option<usize>,
+}
+
+struct Round {
+    sim: Simulation,
+    turns: Vec<InputQueue>,
+    /// The state the clients last saw, to send them what's changed since
+    seen: Snapshot,
+    next_tick: Instant,
+}
+
+/// Runs games for clients connecting over TCP. The server keeps the only
+/// real copy of the game; clients send their turns and are sent what changed
+/// after every tick.
+///
+/// Between rounds the clients wait in a lobby, and a round starts once
+/// enough of them have joined and all of them are ready. Anyone joining
+/// mid-round waits for the next one, and a player who disconnects mid-round
+/// is out of it.
+///
+/// Spectators can connect at any time to watch. Joining mid-round, they're
+/// sent the whole game as it stands and then the same changes as the players.
+pub struct Server {
+    listener: TcpListener,
+    settings: GameSettings,
+    seed: Option<u64>,
+    /// How many players a round needs, and how many the lobby can hold
+    players: RangeInclusive<usize>,
+    clients: BTreeMap<ClientId, Client>,
+    round: Option<Round>,
+}
+
+impl Server {
+    /// Starts listening on `addr`, playing with `settings` and using `seed`
+    /// for every round if given. Rounds are played by as many of `players`
+    /// as have joined, each starting on a row of their own, so the board
+    /// needs at least as many rows as the most players.
+    pub fn bind(
+        addr: impl ToSocketAddrs,
+        settings: GameSettings,
+        seed: Option<u64>,
+        players: RangeInclusive<usize>,
+    ) -> anyhow::Result<Self> {
+        if players.is_empty() || *players.start() < 1 || *players.end() > MAX_PLAYERS {
+            bail!("Rounds can have between 1 and {MAX_PLAYERS} players, not {players:?}");
+        }
+        let size = settings.board.unwrap_or(DEFAULT_BOARD);
+        if size.width > MAX_BOARD.width || size.height > MAX_BOARD.height {
+            bail!(
+                "A {}x{} board is too big, the most a server plays on is {}x{}",
+                size.width,
+                size.height,
+                MAX_BOARD.width,
+                MAX_BOARD.height
+            );
+        }
+        if size.height < *players.end() {
+            bail!(
+                "A {}x{} board only has room for {} players, not {}",
+                size.width,
+                size.height,
+                size.height,
+                players.end()
+            );
+        }
+        let listener = TcpListener::bind(addr).context("Failed to start the server")?;
+
+        Ok(Server {
+            listener,
+            settings,
+            seed,
+            players,
+            clients: BTreeMap::new(),
+            round: None,
+        })
+    }
+
+    pub fn local_addr(&self) -> anyhow::Result<std::net::SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// Serves clients until the listener fails
+    pub fn run(mut self) -> anyhow::Result<()> {
+        let events = self.spawn_listener()?;
+        loop {
+            let timeout = match &self.round {
+                Some(round) => round.next_tick.saturating_duration_since(Instant::now()),
+                None => LOBBY_POLL,
+            };
+            match events.recv_timeout(timeout) {
+                Ok(event) => self.handle(event),
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => bail!("Stopped accepting connections"),
+            }
+            if self
+                .round
+                .as_ref()
+                .is_some_and(|round| Instant::now() >= round.next_tick)
+            {
+                self.tick();
+            }
+        }
+    }
+
+    /// Accepts connections on a background thread, reading each one on a
+    /// thread of its own
+    fn spawn_listener(&self) -> anyhow::Result<Receiver<Event>> {
+        let listener = self.listener.try_clone()?;
+        let (tx, rx) = channel();
+        thread::spawn(move || {
+            for (id, stream) in (0..).zip(listener.incoming()) {
+                let Ok(stream) = stream else {
+                    continue;
+                };
+                let Ok(reader) = stream.try_clone() else {
+                    continue;
+                };
+                if tx.send(Event::Connected(id, stream)).is_err() {
+                    break;
+                }
+                let tx = tx.clone();
+                thread::spawn(move || read_client(id, reader, tx));
+            }
+        });
+
+        Ok(rx)
+    }
+
+    fn handle(&mut self, event: Event) {
+        match event {
+            Event::Connected(id, stream) => {
+                let _ = stream.set_nodelay(true);
+                // the reader thread reports the connection closing
+                let Ok(outbox) = Outbox::new(stream) else {
+                    return;
+                };
+                self.clients.insert(
+                    id,
+                    Client {
+                        outbox,
+                        name: None,
+                        watching: false,
+                        ready: false,
+                        player: None,
+                    },
+                );
+            }
+            Event::Message(id, msg) => self.handle_message(id, msg),
+            Event::Disconnected(id) => self.disconnect(id),
+        }
+    }
+
+    fn handle_message(&mut self, id: ClientId, msg: ClientMsg) {
+        let Some(client) = self.clients.get_mut(&id) else {
+            return;
+        };
+        match msg {
+            ClientMsg::Hello { version, name } => {
+                if client.name.is_some() || client.watching {
+                    return;
+                }
+                if let Some(reason) = version_mismatch(version) {
+                    self.reject(id, reason);
+                    return;
+                }
+                if self.lobby().len() >= *self.players.end() {
+                    self.reject(id, "The server is full".to_string());
+                    return;
+                }
+                eprintln!("{name} joined");
+                if let Some(client) = self.clients.get_mut(&id) {
+                    client.name = Some(name);
+                }
+                self.send_lobby();
+            }
+            ClientMsg::Watch { version } => {
+                if client.name.is_some() || client.watching {
+                    return;
+                }
+                if let Some(reason) = version_mismatch(version) {
+                    self.reject(id, reason);
+                    return;
+                }
+                eprintln!("A spectator joined");
+                client.watching = true;
+                let lobby = ServerMsg::Lobby {
+                    players: self.lobby(),
+                    in_round: self.round.is_some(),
+                };
+                let client = self.clients.get_mut(&id).unwrap();
+                send_to(client, &lobby);
+                if let Some(round) = &self.round {
+                    let start = ServerMsg::Start {
+                        you: None,
+                        config: round.sim.config().clone(),
+                        snapshot: round.seen.clone(),
+                    };
+                    send_to(client, &start);
+                }
+            }
+            ClientMsg::Ready(ready) => {
+                if client.name.is_none() || client.ready == ready {
+                    return;
+                }
+                client.ready = ready;
+                self.send_lobby();
+                self.try_start();
+            }
+            ClientMsg::Input(input) => {
+                let (Some(player), Some(round)) = (client.player, &mut self.round) else {
+                    return;
+                };
+                if let Some(dir) = input.dir() {
+                    let heading = round.sim.players()[player].snake.head().dir;
+                    round.turns[player].push(dir, heading);
+                }
+            }
+        }
+    }
+
+    fn disconnect(&mut self, id: ClientId) {
+        let Some(client) = self.clients.remove(&id) else {
+            return;
+        };
+        if let Some(name) = &client.name {
+            eprintln!("{name} left");
+        }
+        if let (Some(player), Some(round)) = (client.player, &mut self.round) {
+            let state = round.sim.forfeit(player, DeathCause::Timeout);
+            self.send_changes();
+            if state != GameState::Continue {
+                self.end_round(state);
+            }
+        }
+        self.send_lobby();
+        // everyone left might have been waiting on them
+        self.try_start();
+    }
+
+    /// Tells a client why it can't join and hangs up
+    fn reject(&mut self, id: ClientId, reason: String) {
+        if let Some(client) = self.clients.remove(&id) {
+            // dropping the outbox hangs up once the error's been sent
+            client.outbox.send(&ServerMsg::Error(reason));
+        }
+    }
+
+    /// Everyone who's joined, in the order they did
+    fn lobby(&self) -> Vec<LobbyEntry> {
+        self.clients
+            .values()
+            .filter_map(|client| {
+                Some(LobbyEntry {
+                    name: client.name.clone()?,
+                    ready: client.ready,
+                })
+            })
+            .collect()
+    }
+
+    fn send_lobby(&mut self) {
+        let msg = ServerMsg::Lobby {
+            players: self.lobby(),
+            in_round: self.round.is_some(),
+        };
+        for client in self.clients.values_mut() {
+            if client.name.is_some() || client.watching {
+                send_to(client, &msg);
+            }
+        }
+    }
+
+    /// Starts a round if nothing's being played, and enough players have
+    /// joined and are all ready
+    fn try_start(&mut self) {
+        let lobby = self.lobby();
+        if self.round.is_some()
+            || lobby.len() < *self.players.start()
+            || !lobby.iter().all(|entry| entry.ready)
+        {
+            return;
+        }
+
+        let size = self.settings.board.unwrap_or(DEFAULT_BOARD);
+        let config = SimConfig {
+            board: Board::new(size.width + 2, size.height + 2, self.settings.wall_mode),
+            starting_length: self.settings.starting_length,
+            seed: self.seed.unwrap_or_else(rand::random),
+            progressive: self.settings.progressive,
+            map: None,
+            players: lobby.len(),
+        };
+        let sim = Simulation::new(config.clone());
+        let snapshot = sim.snapshot();
+        let players = self.clients.values_mut().filter(|c| c.name.is_some());
+        for (player, client) in players.enumerate() {
+            client.player = Some(player);
+            client.ready = false;
+            let msg = ServerMsg::Start {
+                you: Some(player),
+                config: config.clone(),
+                snapshot: snapshot.clone(),
+            };
+            send_to(client, &msg);
+        }
+        let msg = ServerMsg::Start {
+            you: None,
+            config,
+            snapshot: snapshot.clone(),
+        };
+        for client in self.clients.values_mut().filter(|c| c.watching) {
+            send_to(client, &msg);
+        }
+        eprintln!("Round started with {} players", lobby.len());
+
+        self.round = Some(Round {
+            turns: (0..lobby.len()).map(|_| InputQueue::new()).collect(),
+            next_tick: Instant::now() + self.tick_len(&sim),
+            seen: snapshot,
+            sim,
+        });
+    }
+
+    fn tick_len(&self, sim: &Simulation) -> Duration {
+        Duration::from_secs_f64(self.settings.tick_secs(sim.level()))
+    }
+
+    fn tick(&mut self) {
+        let Some(round) = &mut self.round else {
+            return;
+        };
+        let inputs: Vec<UserInput> = round
+            .turns
+            .iter_mut()
+            .zip(round.sim.players())
+            .map(|(turns, player)| turns.next(player.snake.head().dir).into())
+            .collect();
+        let state = round.sim.update(&inputs);
+        let tick_len = self.tick_len(&self.round.as_ref().unwrap().sim);
+        self.send_changes();
+        if state == GameState::Continue {
+            if let Some(round) = &mut self.round {
+                round.next_tick += tick_len;
+            }
+        } else {
+            self.end_round(state);
+            self.send_lobby();
+        }
+    }
+
+    /// Sends the players what changed since they last heard
+    fn send_changes(&mut self) {
+        let Some(round) = &mut self.round else {
+            return;
+        };
+        let snapshot = round.sim.snapshot();
+        let msg = ServerMsg::Tick(round.seen.delta(&snapshot));
+        round.seen = snapshot;
+        for client in self.clients.values_mut() {
+            if client.player.is_some() || client.watching {
+                send_to(client, &msg);
+            }
+        }
+    }
+
+    fn end_round(&mut self, state: GameState) {
+        self.round = None;
+        let msg = ServerMsg::Over(state);
+        for client in self.clients.values_mut() {
+            if client.player.take().is_some() || client.watching {
+                send_to(client, &msg);
+            }
+        }
+        eprintln!("Round over");
+    }
+}
+
+/// Why a client speaking `version` of the protocol can't join, if it can't
+fn version_mismatch(version: u32) -> Option<String> {
+    (version != PROTOCOL_VERSION)
+        .then(|| format!("The server speaks version {PROTOCOL_VERSION}, not {version}"))
+}
+
+/// Queues a message for a client. If it's fallen too far behind, it's hung
+/// up on and its reader thread reports the disconnect.
+fn send_to(client: &Client, msg: &ServerMsg) {
+    client.outbox.send(msg);
+}
+
+/// Passes on everything a client sends until it disconnects or sends
+/// something that isn't a message. Clients that don't open with a hello or
+/// watch in time are hung up on.
+fn read_client(id: ClientId, stream: TcpStream, events: Sender<Event>) {
+    let _ = stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT));
+    let mut reader = BufReader::new(stream);
+    let mut greeted = false;
+    while let Ok(Some(msg)) = ClientMsg::read(&mut reader) {
+        if !greeted {
+            let greeting = matches!(msg, ClientMsg::Hello { .. } | ClientMsg::Watch { .. });
+            if !greeting || reader.get_ref().set_read_timeout(None).is_err() {
+                break;
+            }
+            greeted = true;
+        }
+        if events.send(Event::Message(id, msg)).is_err() {
+            return;
+        }
+    }
+    let _ = events.send(Event::Disconnected(id));
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::BufRead;
+
+    use crate::net::send;
+
+    use super::*;
+
+    struct TestClient {
+        stream: TcpStream,
+        reader: BufReader<TcpStream>,
+    }
+
+    impl TestClient {
+        fn connect(addr: std::net::SocketAddr, hello: ClientMsg) -> Self {
+            let stream = TcpStream::connect(addr).unwrap();
+            stream
+                .set_read_timeout(Some(Duration::from_secs(5)))
+                .unwrap();
+            let mut client = TestClient {
+                reader: BufReader::new(stream.try_clone().unwrap()),
+                stream,
+            };
+            client.send(hello);
+
+            client
+        }
+
+        fn join(addr: std::net::SocketAddr, name: &str) -> Self {
+            let hello = ClientMsg::Hello {
+                version: PROTOCOL_VERSION,
+                name: name.to_string(),
+            };
+            Self::connect(addr, hello)
+        }
+
+        fn watch(addr: std::net::SocketAddr) -> Self {
+            let hello = ClientMsg::Watch {
+                version: PROTOCOL_VERSION,
+            };
+            Self::connect(addr, hello)
+        }
+
+        fn send(&mut self, msg: ClientMsg) {
+            send(&mut self.stream, &msg).unwrap();
+        }
+
+        /// Reads messages until one matches, panicking if none does in time
+        fn wait_for(&mut self, matches: impl Fn(&ServerMsg) -> bool) -> ServerMsg {
+            loop {
+                let msg = ServerMsg::read(&mut self.reader).unwrap().unwrap();
+                if matches(&msg) {
+                    return msg;
+                }
+            }
+        }
+
+        fn is_closed(&mut self) -> bool {
+            matches!(self.reader.fill_buf(), Ok([]))
+        }
+    }
+
+    fn start_server(players: RangeInclusive<usize>) -> std::net::SocketAddr {
+        let settings = GameSettings {
+            // slow enough that nobody hits a wall during a test
+            tick_ms: Some(1000.0),
+            ..GameSettings::default()
+        };
+        let server = Server::bind("127.0.0.1:0", settings, Some(1), players).unwrap();
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || server.run());
+
+        addr
+    }
+
+    #[test]
+    fn round_starts_once_everyone_is_ready() {
+        let addr = start_server(2..=4);
+        let mut alice = TestClient::join(addr, "alice");
+        let mut bob = TestClient::join(addr, "bob");
+        alice.send(ClientMsg::Ready(true));
+        bob.wait_for(|msg| matches!(msg, ServerMsg::Lobby { players, .. } if players.len() == 2));
+        bob.send(ClientMsg::Ready(true));
+
+        let ServerMsg::Start {
+            you,
+            config,
+            snapshot,
+        } = bob.wait_for(|msg| matches!(msg, ServerMsg::Start { .. }))
+        else {
+            unreachable!()
+        };
+        assert_eq!(you, Some(1));
+        assert_eq!(config.players, 2);
+        assert_eq!(snapshot, Simulation::new(config).snapshot());
+        let start = alice.wait_for(|msg| matches!(msg, ServerMsg::Start { .. }));
+        assert!(matches!(start, ServerMsg::Start { you: Some(0), .. }));
+    }
+
+    #[test]
+    fn leaving_mid_round_hands_the_win_to_whoever_is_left() {
+        let addr = start_server(2..=4);
+        let mut alice = TestClient::join(addr, "alice");
+        let mut bob = TestClient::join(addr, "bob");
+        bob.wait_for(|msg| matches!(msg, ServerMsg::Lobby { players, .. } if players.len() == 2));
+        alice.send(ClientMsg::Ready(true));
+        bob.send(ClientMsg::Ready(true));
+        alice.wait_for(|msg| matches!(msg, ServerMsg::Start { .. }));
+        bob.wait_for(|msg| matches!(msg, ServerMsg::Start { .. }));
+        drop(alice);
+
+        let ServerMsg::Tick(delta) = bob.wait_for(|msg| matches!(msg, ServerMsg::Tick(_))) else {
+            unreachable!()
+        };
+        let death = delta.players[0].death.unwrap();
+        assert_eq!(
+            (delta.players[0].player, death.cause),
+            (0, DeathCause::Timeout)
+        );
+        let over = bob.wait_for(|msg| matches!(msg, ServerMsg::Over(_)));
+        assert_eq!(
+            over,
+            ServerMsg::Over(GameState::RoundOver { winner: Some(1) })
+        );
+        let lobby = bob.wait_for(|msg| matches!(msg, ServerMsg::Lobby { .. }));
+        assert!(
+            matches!(lobby, ServerMsg::Lobby { in_round: false, players } if players.len() == 1)
+        );
+    }
+
+    #[test]
+    fn spectators_joining_mid_round_catch_up_then_follow_along() {
+        let addr = start_server(2..=4);
+        let mut alice = TestClient::join(addr, "alice");
+        let mut bob = TestClient::join(addr, "bob");
+        bob.wait_for(|msg| matches!(msg, ServerMsg::Lobby { players, .. } if players.len() == 2));
+        alice.send(ClientMsg::Ready(true));
+        bob.send(ClientMsg::Ready(true));
+        alice.wait_for(|msg| matches!(msg, ServerMsg::Start { .. }));
+        let ServerMsg::Start { snapshot, .. } =
+            bob.wait_for(|msg| matches!(msg, ServerMsg::Start { .. }))
+        else {
+            unreachable!()
+        };
+
+        let mut carol = TestClient::watch(addr);
+        let lobby = carol.wait_for(|msg| matches!(msg, ServerMsg::Lobby { .. }));
+        assert!(matches!(lobby, ServerMsg::Lobby { in_round: true, .. }));
+        let start = carol.wait_for(|msg| matches!(msg, ServerMsg::Start { .. }));
+        assert!(
+            matches!(start, ServerMsg::Start { you: None, snapshot: seen, .. } if seen == snapshot)
+        );
+
+        drop(alice);
+        let tick = bob.wait_for(|msg| matches!(msg, ServerMsg::Tick(_)));
+        assert_eq!(
+            carol.wait_for(|msg| matches!(msg, ServerMsg::Tick(_))),
+            tick
+        );
+        let over = carol.wait_for(|msg| matches!(msg, ServerMsg::Over(_)));
+        assert_eq!(
+            over,
+            ServerMsg::Over(GameState::RoundOver { winner: Some(1) })
+        );
+    }
+
+    #[test]
+    fn boards_need_a_row_for_every_player() {
+        let settings = GameSettings {
+            board: Some(BoardSize {
+                width: 40,
+                height: 2,
+            }),
+            ..GameSettings::default()
+        };
+        let error = Server::bind("127.0.0.1:0", settings.clone(), None, 2..=8)
+            .err()
+            .unwrap();
+
+        assert_eq!(
+            error.to_string(),
+            "A 40x2 board only has room for 2 players, not 8"
+        );
+        assert!(Server::bind("127.0.0.1:0", settings, None, 2..=2).is_ok());
+    }
+
+    #[test]
+    fn boards_too_big_to_send_are_refused() {
+        let board = |width, height| GameSettings {
+            board: Some(BoardSize { width, height }),
+            ..GameSettings::default()
+        };
+        let wide = board(MAX_BOARD.width + 1, 20);
+        let tall = board(40, MAX_BOARD.height + 1);
+
+        assert!(Server::bind("127.0.0.1:0", wide, None, 2..=4).is_err());
+        assert!(Server::bind("127.0.0.1:0", tall, None, 2..=4).is_err());
+        let largest = board(MAX_BOARD.width, MAX_BOARD.height);
+        assert!(Server::bind("127.0.0.1:0", largest, None, 2..=4).is_ok());
+    }
+
+    #[test]
+    fn clients_have_to_say_hello_first() {
+        let addr = start_server(2..=4);
+        let mut client = TestClient::connect(addr, ClientMsg::Ready(true));
+
+        assert!(client.is_closed());
+    }
+
+    #[test]
+    fn full_server_turns_players_away() {
+        let addr = start_server(1..=1);
+        let mut alice = TestClient::join(addr, "alice");
+        alice.wait_for(|msg| matches!(msg, ServerMsg::Lobby { .. }));
+        let mut bob = TestClient::join(addr, "bob");
+
+        let error = bob.wait_for(|_| true);
+        assert_eq!(error, ServerMsg::Error("The server is full".to_string()));
+        assert!(bob.is_closed());
+    }
+}
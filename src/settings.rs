@@ -0,0 +1,895 @@
+use std::{fs, path::PathBuf};
+
+use anyhow::{anyhow, bail, Context};
+use console::Key;
+use serde::{Deserialize, Serialize};
+use toml_edit::{DocumentMut, Item};
+
+use crate::{input::UserInput, render::CellShape, sim::WallMode};
+
+/// Terminal colors that can be named in the config file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Color {
+    Black,
+    Red,
+    Green,
+    Yellow,
+    Blue,
+    Magenta,
+    Cyan,
+    White,
+}
+
+impl Color {
+    /// The color's number in the standard 8-color ANSI palette
+    pub fn ansi_index(&self) -> u8 {
+        match self {
+            Color::Black => 0,
+            Color::Red => 1,
+            Color::Green => 2,
+            Color::Yellow => 3,
+            Color::Blue => 4,
+            Color::Magenta => 5,
+            Color::Cyan => 6,
+            Color::White => 7,
+        }
+    }
+}
+
+/// Foreground and background colors for one kind of cell, with `None` leaving
+/// the terminal's default in place
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CellStyle {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fg: Option<Color>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bg: Option<Color>,
+}
+
+impl CellStyle {
+    pub const fn new(fg: Color, bg: Color) -> Self {
+        CellStyle {
+            fg: Some(fg),
+            bg: Some(bg),
+        }
+    }
+
+    /// A foreground color on the terminal's default background
+    pub const fn fg(fg: Color) -> Self {
+        CellStyle {
+            fg: Some(fg),
+            bg: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Colors {
+    pub snake: CellStyle,
+    /// The second player's snake
+    pub rival: CellStyle,
+    pub apple: CellStyle,
+    pub border: CellStyle,
+    pub score_bar: CellStyle,
+}
+
+impl Default for Colors {
+    fn default() -> Self {
+        Colors {
+            snake: CellStyle::new(Color::Green, Color::White),
+            rival: CellStyle::new(Color::Magenta, Color::White),
+            apple: CellStyle::new(Color::Red, Color::Black),
+            border: CellStyle::default(),
+            score_bar: CellStyle::new(Color::Black, Color::White),
+        }
+    }
+}
+
+/// Keys written by name in the config file. Any other key is written as the
+/// character it types.
+const NAMED_KEYS: [(&str, Key); 16] = [
+    ("ArrowUp", Key::ArrowUp),
+    ("ArrowDown", Key::ArrowDown),
+    ("ArrowLeft", Key::ArrowLeft),
+    ("ArrowRight", Key::ArrowRight),
+    ("Escape", Key::Escape),
+    ("Enter", Key::Enter),
+    ("Tab", Key::Tab),
+    ("BackTab", Key::BackTab),
+    ("Backspace", Key::Backspace),
+    ("Home", Key::Home),
+    ("End", Key::End),
+    ("Del", Key::Del),
+    ("Insert", Key::Insert),
+    ("PageUp", Key::PageUp),
+    ("PageDown", Key::PageDown),
+    ("Space", Key::Char(' ')),
+];
+
+/// A key that can be bound, as written in the config file, e.g. `"ArrowUp"`,
+/// `"Escape"` or `"w"`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct KeyName(Key);
+
+impl KeyName {
+    /// The key, if it has a name it can be saved under. Keys the terminal
+    /// couldn't make out, modifiers on their own, Ctrl-C and control
+    /// characters can't be bound.
+    pub fn new(key: Key) -> Option<Self> {
+        let named = NAMED_KEYS.iter().any(|(_, named)| *named == key);
+        match key {
+            _ if named => Some(KeyName(key)),
+            Key::Char(c) if !c.is_control() => Some(KeyName(key)),
+            _ => None,
+        }
+    }
+
+    pub fn key(&self) -> &Key {
+        &self.0
+    }
+}
+
+impl TryFrom<String> for KeyName {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        if let Some((_, key)) = NAMED_KEYS.iter().find(|(name, _)| *name == value) {
+            return Ok(KeyName(key.clone()));
+        }
+        let mut chars = value.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => KeyName::new(Key::Char(c)),
+            _ => None,
+        }
+        .ok_or_else(|| anyhow!("Unknown key name '{value}'"))
+    }
+}
+
+impl From<KeyName> for String {
+    fn from(value: KeyName) -> Self {
+        if let Some((name, _)) = NAMED_KEYS.iter().find(|(_, key)| *key == value.0) {
+            return name.to_string();
+        }
+        match value.0 {
+            Key::Char(c) => c.to_string(),
+            key => unreachable!("KeyName::new only takes keys with names, not {key:?}"),
+        }
+    }
+}
+
+impl std::fmt::Display for KeyName {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", String::from(self.clone()))
+    }
+}
+
+/// Sets of keys to start the bindings from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum KeyPreset {
+    #[default]
+    Arrows,
+    Wasd,
+    /// `h`, `j`, `k` and `l`, as in vi
+    Vim,
+}
+
+impl KeyPreset {
+    pub const ALL: [KeyPreset; 3] = [KeyPreset::Arrows, KeyPreset::Wasd, KeyPreset::Vim];
+}
+
+impl std::fmt::Display for KeyPreset {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            KeyPreset::Arrows => write!(f, "arrows"),
+            KeyPreset::Wasd => write!(f, "wasd"),
+            KeyPreset::Vim => write!(f, "vim"),
+        }
+    }
+}
+
+/// The inputs keys can be bound to, with the names used for them in menus
+pub const BOUND_INPUTS: [(UserInput, &str); 5] = [
+    (UserInput::Up, "Up"),
+    (UserInput::Down, "Down"),
+    (UserInput::Left, "Left"),
+    (UserInput::Right, "Right"),
+    (UserInput::Pause, "Pause"),
+];
+
+/// The keys for each in game input, any of which can be pressed for it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(from = "KeysConfig", into = "KeysConfig")]
+pub struct KeyBindings {
+    pub preset: KeyPreset,
+    pub up: Vec<KeyName>,
+    pub down: Vec<KeyName>,
+    pub left: Vec<KeyName>,
+    pub right: Vec<KeyName>,
+    pub pause: Vec<KeyName>,
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        KeyBindings::from_preset(KeyPreset::Arrows)
+    }
+}
+
+impl KeyBindings {
+    pub fn from_preset(preset: KeyPreset) -> Self {
+        let keys = |keys: &[Key]| keys.iter().cloned().map(KeyName).collect();
+        let [up, down, left, right] = match preset {
+            KeyPreset::Arrows => [
+                Key::ArrowUp,
+                Key::ArrowDown,
+                Key::ArrowLeft,
+                Key::ArrowRight,
+            ],
+            KeyPreset::Wasd => [
+                Key::Char('w'),
+                Key::Char('s'),
+                Key::Char('a'),
+                Key::Char('d'),
+            ],
+            KeyPreset::Vim => [
+                Key::Char('k'),
+                Key::Char('j'),
+                Key::Char('h'),
+                Key::Char('l'),
+            ],
+        };
+        KeyBindings {
+            preset,
+            up: keys(&[up]),
+            down: keys(&[down]),
+            left: keys(&[left]),
+            right: keys(&[right]),
+            pause: keys(&[Key::Escape]),
+        }
+    }
+
+    /// The keys bound to `input`, which is empty for `UserInput::Unknown`
+    pub fn keys(&self, input: UserInput) -> &[KeyName] {
+        match input {
+            UserInput::Up => &self.up,
+            UserInput::Down => &self.down,
+            UserInput::Left => &self.left,
+            UserInput::Right => &self.right,
+            UserInput::Pause => &self.pause,
+            UserInput::Unknown => &[],
+        }
+    }
+
+    /// The keys bound to `input`, or `None` for `UserInput::Unknown`
+    pub fn keys_mut(&mut self, input: UserInput) -> Option<&mut Vec<KeyName>> {
+        match input {
+            UserInput::Up => Some(&mut self.up),
+            UserInput::Down => Some(&mut self.down),
+            UserInput::Left => Some(&mut self.left),
+            UserInput::Right => Some(&mut self.right),
+            UserInput::Pause => Some(&mut self.pause),
+            UserInput::Unknown => None,
+        }
+    }
+
+    pub fn input_for(&self, key: &Key) -> UserInput {
+        BOUND_INPUTS
+            .iter()
+            .map(|(input, _)| *input)
+            .find(|input| self.keys(*input).iter().any(|k| k.key() == key))
+            .unwrap_or(UserInput::Unknown)
+    }
+
+    /// Checks every input has a key, and that no key is bound to two inputs
+    pub fn validate(&self) -> anyhow::Result<()> {
+        for (i, (input, name)) in BOUND_INPUTS.iter().enumerate() {
+            let keys = self.keys(*input);
+            if keys.is_empty() {
+                bail!("No keys are bound to {name}");
+            }
+            for key in keys {
+                for (other, other_name) in &BOUND_INPUTS[i + 1..] {
+                    if self.keys(*other).contains(key) {
+                        bail!("{key} is bound to both {name} and {other_name}");
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// One key or a list of them, as written in the config file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+enum OneOrMany {
+    One(KeyName),
+    Many(Vec<KeyName>),
+}
+
+impl From<OneOrMany> for Vec<KeyName> {
+    fn from(value: OneOrMany) -> Self {
+        match value {
+            OneOrMany::One(key) => vec![key],
+            OneOrMany::Many(keys) => keys,
+        }
+    }
+}
+
+/// The `[keys]` config table: a preset, with any inputs listed replacing the
+/// preset's keys for them
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct KeysConfig {
+    preset: KeyPreset,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    up: Option<OneOrMany>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    down: Option<OneOrMany>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    left: Option<OneOrMany>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    right: Option<OneOrMany>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pause: Option<OneOrMany>,
+}
+
+impl From<KeysConfig> for KeyBindings {
+    fn from(value: KeysConfig) -> Self {
+        let mut keys = KeyBindings::from_preset(value.preset);
+        let overrides = [value.up, value.down, value.left, value.right, value.pause];
+        for ((input, _), keys_override) in BOUND_INPUTS.iter().zip(overrides) {
+            if let (Some(keys), Some(keys_override)) = (keys.keys_mut(*input), keys_override) {
+                *keys = keys_override.into();
+            }
+        }
+
+        keys
+    }
+}
+
+impl From<KeyBindings> for KeysConfig {
+    fn from(value: KeyBindings) -> Self {
+        // only write out what differs from the preset
+        let preset = KeyBindings::from_preset(value.preset);
+        let diff = |input| {
+            let keys = value.keys(input);
+            (keys != preset.keys(input)).then(|| OneOrMany::Many(keys.to_vec()))
+        };
+        KeysConfig {
+            preset: value.preset,
+            up: diff(UserInput::Up),
+            down: diff(UserInput::Down),
+            left: diff(UserInput::Left),
+            right: diff(UserInput::Right),
+            pause: diff(UserInput::Pause),
+        }
+    }
+}
+
+/// Size of the playfield in cells, not counting the border around it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BoardSize {
+    pub width: usize,
+    pub height: usize,
+}
+
+impl std::str::FromStr for BoardSize {
+    type Err = anyhow::Error;
+
+    /// Parses sizes written as `<width>x<height>`, e.g. `40x20`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (w, h) = s
+            .split_once('x')
+            .ok_or_else(|| anyhow!("Board size must look like <width>x<height>"))?;
+        Ok(BoardSize {
+            width: w.parse().context("Invalid board width")?,
+            height: h.parse().context("Invalid board height")?,
+        })
+    }
+}
+
+/// Presets for how fast the game runs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Difficulty {
+    Easy,
+    #[default]
+    Normal,
+    Hard,
+    Insane,
+}
+
+impl Difficulty {
+    pub const ALL: [Difficulty; 4] = [
+        Difficulty::Easy,
+        Difficulty::Normal,
+        Difficulty::Hard,
+        Difficulty::Insane,
+    ];
+
+    /// Length of one tick at speed level 1, in milliseconds
+    pub fn tick_ms(&self) -> f64 {
+        match self {
+            Difficulty::Easy => 100.0,
+            Difficulty::Normal => 62.5,
+            Difficulty::Hard => 40.0,
+            Difficulty::Insane => 25.0,
+        }
+    }
+}
+
+impl std::fmt::Display for Difficulty {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Difficulty::Easy => write!(f, "easy"),
+            Difficulty::Normal => write!(f, "normal"),
+            Difficulty::Hard => write!(f, "hard"),
+            Difficulty::Insane => write!(f, "insane"),
+        }
+    }
+}
+
+impl std::str::FromStr for Difficulty {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "easy" => Ok(Difficulty::Easy),
+            "normal" => Ok(Difficulty::Normal),
+            "hard" => Ok(Difficulty::Hard),
+            "insane" => Ok(Difficulty::Insane),
+            _ => bail!("Unknown difficulty '{s}' (expected easy, normal, hard or insane)"),
+        }
+    }
+}
+
+/// Each speed level above the first shortens the tick by this factor
+const LEVEL_SPEEDUP: f64 = 0.85;
+/// Ticks never get shorter than this, however high the level
+const MIN_TICK_MS: f64 = 15.0;
+/// The longest tick that can be asked for, a minute
+pub const MAX_TICK_MS: f64 = 60_000.0;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GameSettings {
+    pub difficulty: Difficulty,
+    /// Length of one game tick in milliseconds, overriding the difficulty's
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tick_ms: Option<f64>,
+    /// Speed up as the score rises
+    pub progressive: bool,
+    /// Fixed playfield size, or `None` to fill the terminal
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub board: Option<BoardSize>,
+    pub starting_length: usize,
+    pub wall_mode: WallMode,
+    /// Draw with plain ASCII and no colors
+    pub plain: bool,
+    pub cell_shape: CellShape,
+    pub colors: Colors,
+    pub keys: KeyBindings,
+}
+
+impl Default for GameSettings {
+    fn default() -> Self {
+        GameSettings {
+            difficulty: Difficulty::Normal,
+            tick_ms: None,
+            progressive: false,
+            board: None,
+            starting_length: 1,
+            wall_mode: WallMode::Solid,
+            plain: false,
+            cell_shape: CellShape::Narrow,
+            colors: Colors::default(),
+            keys: KeyBindings::default(),
+        }
+    }
+}
+
+impl GameSettings {
+    /// The default config file location, e.g. `~/.config/rusty_snake/config.toml`
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("rusty_snake").join("config.toml"))
+    }
+
+    /// Loads settings from `path`, falling back to the defaults if it doesn't exist
+    pub fn load(path: &std::path::Path) -> anyhow::Result<Self> {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        };
+        let settings: Self = toml::from_str(&contents)
+            .with_context(|| format!("Invalid config file {}", path.display()))?;
+        settings.validate()?;
+
+        Ok(settings)
+    }
+
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if let Some(tick_ms) = self.tick_ms {
+            if !tick_ms.is_finite() || tick_ms <= 0.0 || tick_ms > MAX_TICK_MS {
+                bail!("tick_ms must be greater than 0 and at most {MAX_TICK_MS}");
+            }
+        }
+        if self.starting_length == 0 {
+            bail!("starting_length must be at least 1");
+        }
+        if let Some(board) = self.board {
+            if board.width < 2 || board.height < 2 {
+                bail!("board must be at least 2x2");
+            }
+        }
+        self.keys.validate()?;
+
+        Ok(())
+    }
+
+    /// Saves `keys` as the `[keys]` table of the config file at `path`,
+    /// leaving the rest of the file, comments and all, as it is
+    pub fn save_keys(path: &std::path::Path, keys: &KeyBindings) -> anyhow::Result<()> {
+        let keys: DocumentMut = toml::to_string(keys)?.parse()?;
+        edit_config(path, |config| {
+            config["keys"] = Item::Table(keys.as_table().clone());
+        })
+    }
+
+    /// Saves the named top level `fields`, such as `"board"`, to the config
+    /// file at `path`, leaving the rest of the file as it is. Fields this
+    /// leaves out keep what the file had, even where a command line flag
+    /// has overridden them.
+    pub fn save_rules(&self, path: &std::path::Path, fields: &[&str]) -> anyhow::Result<()> {
+        let saved: DocumentMut = toml::to_string(self)?.parse()?;
+        edit_config(path, |config| {
+            for &field in fields {
+                match saved.get(field) {
+                    Some(item) => config[field] = item.clone(),
+                    None => {
+                        config.remove(field);
+                    }
+                }
+            }
+        })
+    }
+
+    /// Length of a tick at speed level 1, in milliseconds
+    pub fn base_tick_ms(&self) -> f64 {
+        self.tick_ms.unwrap_or_else(|| self.difficulty.tick_ms())
+    }
+
+    /// Length of a tick at the given speed level, in seconds
+    pub fn tick_secs(&self, level: usize) -> f64 {
+        let base = self.base_tick_ms();
+        let speedup = LEVEL_SPEEDUP.powi(level.saturating_sub(1) as i32);
+        (base * speedup).max(MIN_TICK_MS.min(base)) / 1000.0
+    }
+
+    /// A summary of the settings for display in the menus
+    pub fn describe(&self) -> Vec<String> {
+        let board = match self.board {
+            Some(size) => format!("{}x{}", size.width, size.height),
+            None => "fit terminal".to_string(),
+        };
+        let speed = match self.tick_ms {
+            Some(tick_ms) => format!("{tick_ms}ms ticks"),
+            None => self.difficulty.to_string(),
+        };
+        vec![
+            format!("Speed: {speed}"),
+            format!(
+                "Progressive: {}",
+                if self.progressive { "on" } else { "off" }
+            ),
+            format!("Board: {board}"),
+            format!("Starting length: {}", self.starting_length),
+            format!("Walls: {}", self.wall_mode),
+            format!("Cells: {}", self.cell_shape),
+        ]
+    }
+}
+
+/// Applies `edit` to the config file at `path`, creating it if need be
+/// and keeping comments and formatting of whatever `edit` leaves alone
+fn edit_config(path: &std::path::Path, edit: impl FnOnce(&mut DocumentMut)) -> anyhow::Result<()> {
+    let mut config = match fs::read_to_string(path) {
+        Ok(contents) => contents
+            .parse::<DocumentMut>()
+            .with_context(|| format!("Invalid config file {}", path.display()))?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => DocumentMut::new(),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    };
+    edit(&mut config);
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    }
+    fs::write(path, config.to_string())
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+
+    Ok(())
+}
+
+/// The value following a command line flag
+pub fn flag_value(argv: &mut impl Iterator<Item = String>, flag: &str) -> anyhow::Result<String> {
+    argv.next()
+        .ok_or_else(|| anyhow!("{flag} requires a value"))
+}
+
+/// Game rules given on the command line, overriding the config file. Both the
+/// game and the server take these.
+#[derive(Debug, Clone, Default)]
+pub struct RuleOverrides {
+    pub tick_ms: Option<f64>,
+    pub board: Option<BoardSize>,
+    pub starting_length: Option<usize>,
+    pub wall_mode: Option<WallMode>,
+    pub difficulty: Option<Difficulty>,
+    pub progressive: bool,
+}
+
+impl RuleOverrides {
+    /// Takes in `flag` if it's one of the rule flags, reading its value from
+    /// `argv`. Returns whether it was.
+    pub fn parse_flag(
+        &mut self,
+        flag: &str,
+        argv: &mut impl Iterator<Item = String>,
+    ) -> anyhow::Result<bool> {
+        match flag {
+            "--tick-ms" => {
+                let val = flag_value(argv, flag)?;
+                self.tick_ms = Some(val.parse().context("--tick-ms must be a number")?);
+            }
+            "--board" => {
+                let val = flag_value(argv, flag)?;
+                self.board = Some(val.parse().context("Invalid --board")?);
+            }
+            "--length" => {
+                let val = flag_value(argv, flag)?;
+                self.starting_length = Some(
+                    val.parse()
+                        .context("--length must be an unsigned integer")?,
+                );
+            }
+            "--walls" => self.wall_mode = Some(flag_value(argv, flag)?.parse()?),
+            "--difficulty" => self.difficulty = Some(flag_value(argv, flag)?.parse()?),
+            "--progressive" => self.progressive = true,
+            _ => return Ok(false),
+        }
+
+        Ok(true)
+    }
+
+    pub fn apply(&self, settings: &mut GameSettings) {
+        if let Some(tick_ms) = self.tick_ms {
+            settings.tick_ms = Some(tick_ms);
+        }
+        if let Some(board) = self.board {
+            settings.board = Some(board);
+        }
+        if let Some(starting_length) = self.starting_length {
+            settings.starting_length = starting_length;
+        }
+        if let Some(wall_mode) = self.wall_mode {
+            settings.wall_mode = wall_mode;
+        }
+        if let Some(difficulty) = self.difficulty {
+            settings.difficulty = difficulty;
+        }
+        if self.progressive {
+            settings.progressive = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::TempDir;
+
+    use super::*;
+
+    /// Writes `config` to a file of its own and loads it
+    fn load_config(name: &str, config: &str) -> anyhow::Result<GameSettings> {
+        let dir = TempDir::new(name);
+        let path = dir.join("config.toml");
+        fs::write(&path, config).unwrap();
+        GameSettings::load(&path)
+    }
+
+    #[test]
+    fn a_missing_config_falls_back_to_the_defaults() {
+        let dir = TempDir::new("missing");
+        let path = dir.join("config.toml");
+
+        assert_eq!(GameSettings::load(&path).unwrap(), GameSettings::default());
+    }
+
+    #[test]
+    fn fields_left_out_of_the_config_keep_their_defaults() {
+        let settings = load_config(
+            "partial",
+            "difficulty = \"hard\"\n[board]\nwidth = 30\nheight = 12\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            settings,
+            GameSettings {
+                difficulty: Difficulty::Hard,
+                board: Some(BoardSize {
+                    width: 30,
+                    height: 12
+                }),
+                ..GameSettings::default()
+            }
+        );
+    }
+
+    #[test]
+    fn command_line_flags_override_the_config() {
+        let mut settings = load_config(
+            "flags",
+            "difficulty = \"hard\"\nstarting_length = 5\nwall_mode = \"wrap\"\n",
+        )
+        .unwrap();
+        let mut rules = RuleOverrides::default();
+        let mut argv = ["easy", "3"].map(String::from).into_iter();
+        assert!(rules.parse_flag("--difficulty", &mut argv).unwrap());
+        assert!(rules.parse_flag("--length", &mut argv).unwrap());
+        assert!(!rules.parse_flag("--plain", &mut argv).unwrap());
+        rules.apply(&mut settings);
+
+        assert_eq!(settings.difficulty, Difficulty::Easy);
+        assert_eq!(settings.starting_length, 3);
+        // what the flags don't mention still comes from the file
+        assert_eq!(settings.wall_mode, WallMode::Wrap);
+    }
+
+    #[test]
+    fn each_level_shortens_the_tick() {
+        let settings = GameSettings {
+            tick_ms: Some(100.0),
+            ..GameSettings::default()
+        };
+        let ticks: Vec<f64> = (1..=4).map(|level| settings.tick_secs(level)).collect();
+
+        for (tick, expected) in ticks.iter().zip([0.1, 0.085, 0.07225, 0.0614125]) {
+            assert!((tick - expected).abs() < 1e-12, "{tick} != {expected}");
+        }
+        // level 0 never happens, but plays like level 1
+        assert_eq!(settings.tick_secs(0), settings.tick_secs(1));
+    }
+
+    #[test]
+    fn ticks_stop_shortening_at_the_minimum() {
+        let settings = GameSettings {
+            tick_ms: Some(100.0),
+            ..GameSettings::default()
+        };
+        assert_eq!(settings.tick_secs(50), MIN_TICK_MS / 1000.0);
+        assert!((1..50).all(|level| settings.tick_secs(level) >= MIN_TICK_MS / 1000.0));
+
+        // a tick set shorter than the minimum stays as it is
+        let fast = GameSettings {
+            tick_ms: Some(10.0),
+            ..GameSettings::default()
+        };
+        assert_eq!(fast.tick_secs(1), 0.01);
+        assert_eq!(fast.tick_secs(10), 0.01);
+    }
+
+    #[test]
+    fn every_key_that_can_be_bound_saves_and_loads() {
+        let mut bindable: Vec<Key> = NAMED_KEYS.iter().map(|(_, key)| key.clone()).collect();
+        bindable.extend("w1;#\"'[=é".chars().map(Key::Char));
+        let mut keys = KeyBindings::from_preset(KeyPreset::Arrows);
+        for (input, _) in BOUND_INPUTS {
+            keys.keys_mut(input).unwrap().clear();
+        }
+        // spread the keys over the inputs so none is bound twice
+        for (key, (input, _)) in bindable.into_iter().zip(BOUND_INPUTS.iter().cycle()) {
+            let name = KeyName::new(key).unwrap();
+            keys.keys_mut(*input).unwrap().push(name);
+        }
+
+        let dir = TempDir::new("all_keys");
+        let path = dir.join("config.toml");
+        GameSettings::save_keys(&path, &keys).unwrap();
+
+        assert_eq!(GameSettings::load(&path).unwrap().keys, keys);
+    }
+
+    #[test]
+    fn keys_without_a_name_cannot_be_bound() {
+        for key in [
+            Key::Unknown,
+            Key::UnknownEscSeq(vec!['[', '9']),
+            Key::CtrlC,
+            Key::Alt,
+            Key::Shift,
+            Key::Char('\u{1}'),
+        ] {
+            assert_eq!(KeyName::new(key.clone()), None, "{key:?}");
+        }
+        assert!(KeyName::try_from("F1".to_string()).is_err());
+        assert!(KeyName::try_from("\u{1}".to_string()).is_err());
+    }
+
+    #[test]
+    fn keys_bound_to_two_inputs_are_refused() {
+        let mut keys = KeyBindings::from_preset(KeyPreset::Wasd);
+        keys.up.push(KeyName::new(Key::Char('s')).unwrap());
+
+        assert_eq!(
+            keys.validate().unwrap_err().to_string(),
+            "s is bound to both Up and Down"
+        );
+
+        let mut keys = KeyBindings::from_preset(KeyPreset::Wasd);
+        keys.pause.clear();
+        assert_eq!(
+            keys.validate().unwrap_err().to_string(),
+            "No keys are bound to Pause"
+        );
+    }
+
+    #[test]
+    fn saving_keys_keeps_the_rest_of_the_config() {
+        let dir = TempDir::new("keys");
+        let path = dir.join("config.toml");
+        let config =
+            "# how fast it goes\ndifficulty = \"hard\" # not insane\n\n[keys]\npreset = \"wasd\"\n";
+        fs::write(&path, config).unwrap();
+
+        let keys = KeyBindings::from_preset(KeyPreset::Vim);
+        GameSettings::save_keys(&path, &keys).unwrap();
+        let saved = fs::read_to_string(&path).unwrap();
+        let settings = GameSettings::load(&path).unwrap();
+
+        assert!(saved.starts_with("# how fast it goes\ndifficulty = \"hard\" # not insane\n"));
+        assert_eq!(settings.difficulty, Difficulty::Hard);
+        assert_eq!(settings.keys, keys);
+    }
+
+    #[test]
+    fn saving_rules_keeps_the_rest_of_the_config() {
+        let dir = TempDir::new("rules");
+        let path = dir.join("config.toml");
+        let config = "# how long it starts\nstarting_length = 4\ntick_ms = 80.0\n\n[keys]\npreset = \"wasd\"\n\n[board]\nwidth = 10\nheight = 10\n";
+        fs::write(&path, config).unwrap();
+
+        let rules = GameSettings {
+            difficulty: Difficulty::Insane,
+            board: None,
+            wall_mode: WallMode::Wrap,
+            cell_shape: CellShape::Square,
+            // as if given on the command line, and not to be saved
+            starting_length: 9,
+            plain: true,
+            ..GameSettings::default()
+        };
+        let edited = ["difficulty", "tick_ms", "board", "wall_mode", "cell_shape"];
+        rules.save_rules(&path, &edited).unwrap();
+        let saved = fs::read_to_string(&path).unwrap();
+        let settings = GameSettings::load(&path).unwrap();
+
+        assert!(saved.starts_with("# how long it starts\nstarting_length = 4\n"));
+        assert!(!saved.contains("tick_ms"));
+        assert_eq!(settings.difficulty, Difficulty::Insane);
+        assert_eq!(settings.tick_ms, None);
+        assert_eq!(settings.board, None);
+        assert_eq!(settings.wall_mode, WallMode::Wrap);
+        assert_eq!(settings.cell_shape, CellShape::Square);
+        assert_eq!(settings.starting_length, 4);
+        assert!(!settings.plain);
+        assert_eq!(settings.keys, KeyBindings::from_preset(KeyPreset::Wasd));
+    }
+}
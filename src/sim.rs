@@ -0,0 +1,981 @@
+use std::{collections::BTreeSet, fmt::Display, str::FromStr, sync::Arc};
+
+use anyhow::bail;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    input::UserInput,
+    maps::Map,
+    snake::{BodySegment, Dir, Snake, TermPoint},
+};
+
+/// What happens when the snake runs into the edge of the board
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WallMode {
+    /// Hitting the border ends the game
+    #[default]
+    Solid,
+    /// Leaving one edge re-enters from the opposite one
+    Wrap,
+}
+
+impl WallMode {
+    pub const ALL: [WallMode; 2] = [WallMode::Solid, WallMode::Wrap];
+}
+
+impl Display for WallMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WallMode::Solid => write!(f, "solid"),
+            WallMode::Wrap => write!(f, "wrap"),
+        }
+    }
+}
+
+impl FromStr for WallMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "solid" => Ok(WallMode::Solid),
+            "wrap" => Ok(WallMode::Wrap),
+            _ => bail!("Unknown wall mode '{s}' (expected solid or wrap)"),
+        }
+    }
+}
+
+/// Dimensions of the playfield, including the one cell wide border around it
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Board {
+    pub width: usize,
+    pub height: usize,
+    pub wall_mode: WallMode,
+}
+
+impl Board {
+    pub fn new(width: usize, height: usize, wall_mode: WallMode) -> Self {
+        Board {
+            width,
+            height,
+            wall_mode,
+        }
+    }
+
+    pub fn is_border(&self, pos: TermPoint) -> bool {
+        pos.row == 0 || pos.row >= self.height - 1 || pos.col == 0 || pos.col >= self.width - 1
+    }
+
+    /// The cell one step from `pos` in direction `dir`.
+    ///
+    /// With solid walls this may be a border cell, but never leaves the board.
+    /// With wrapping walls, stepping onto the border comes out the other side
+    /// of the playfield instead.
+    pub fn step(&self, pos: TermPoint, dir: Dir) -> TermPoint {
+        let (last_row, last_col) = (self.height - 1, self.width - 1);
+        let next = match dir {
+            Dir::Up => TermPoint::new(pos.row.saturating_sub(1), pos.col),
+            Dir::Down => TermPoint::new((pos.row + 1).min(last_row), pos.col),
+            Dir::Left => TermPoint::new(pos.row, pos.col.saturating_sub(1)),
+            Dir::Right => TermPoint::new(pos.row, (pos.col + 1).min(last_col)),
+        };
+        if self.wall_mode == WallMode::Solid || !self.is_border(next) {
+            return next;
+        }
+
+        match dir {
+            Dir::Up => TermPoint::new(last_row - 1, next.col),
+            Dir::Down => TermPoint::new(1, next.col),
+            Dir::Left => TermPoint::new(next.row, last_col - 1),
+            Dir::Right => TermPoint::new(next.row, 1),
+        }
+    }
+}
+
+/// Points for an apple at speed level 1
+pub const POINTS_PER_APPLE: usize = 100;
+/// With progressive speed, the level goes up after eating this many apples
+pub const APPLES_PER_LEVEL: usize = 5;
+pub const MAX_LEVEL: usize = 10;
+
+/// Everything besides the players' input that determines how a game plays out
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SimConfig {
+    pub board: Board,
+    pub starting_length: usize,
+    pub seed: u64,
+    /// Whether the speed level rises as apples are eaten
+    pub progressive: bool,
+    /// Layout of internal walls, which must match the board's size
+    pub map: Option<Arc<Map>>,
+    /// How many snakes share the board, each steered by its own player
+    pub players: usize,
+}
+
+impl SimConfig {
+    /// A game on `board` for `players`, with snakes of 3, seed 0, no map and
+    /// a steady speed
+    pub fn new(board: Board, players: usize) -> Self {
+        SimConfig {
+            board,
+            starting_length: 3,
+            seed: 0,
+            progressive: false,
+            map: None,
+            players,
+        }
+    }
+
+    /// The speed level once the players have eaten `apples` between them
+    pub fn level(&self, apples: usize) -> usize {
+        if self.progressive {
+            (1 + apples / APPLES_PER_LEVEL).min(MAX_LEVEL)
+        } else {
+            1
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GameState {
+    Continue,
+    Over(Death),
+    Win,
+    /// A game with several snakes ended, won by the player at this index, or
+    /// drawn if there's none. The last snake left alive wins, or whoever ate
+    /// the apple that filled the board.
+    RoundOver {
+        winner: Option<usize>,
+    },
+}
+
+/// What a snake ran into
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeathCause {
+    /// The border around the board
+    Wall,
+    /// Its own body, at this index counting from the head
+    SelfCollision { segment: usize },
+    /// One of the map's internal walls
+    Obstacle,
+    /// Another snake's body, at this index counting from its head, so 0 means
+    /// the two collided head on
+    Opponent { segment: usize },
+    /// The player stopped responding
+    Timeout,
+}
+
+impl Display for DeathCause {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DeathCause::Wall => write!(f, "Hit the wall"),
+            DeathCause::SelfCollision { segment } => {
+                write!(f, "Ran into itself (segment {segment})")
+            }
+            DeathCause::Obstacle => write!(f, "Hit an obstacle"),
+            DeathCause::Opponent { segment: 0 } => write!(f, "Crashed head on"),
+            DeathCause::Opponent { segment } => {
+                write!(f, "Ran into the other snake (segment {segment})")
+            }
+            DeathCause::Timeout => write!(f, "Timed out"),
+        }
+    }
+}
+
+/// How and where a game ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Death {
+    pub cause: DeathCause,
+    /// Where the snake's head ended up
+    pub head: TermPoint,
+}
+
+impl Display for Death {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} at {},{}", self.cause, self.head.col, self.head.row)
+    }
+}
+
+/// Everything about a game that changes as it's played. Together with its
+/// `SimConfig` this is enough to draw the game as it stands.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    pub players: Vec<PlayerState>,
+    pub apple: TermPoint,
+}
+
+/// One player's part of a `Snapshot`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlayerState {
+    /// The snake's segments, head first
+    pub body: Vec<BodySegment>,
+    pub score: usize,
+    pub apples_eaten: usize,
+    pub death: Option<Death>,
+}
+
+/// How a `Snapshot` changed from one tick to the next, listing only the
+/// players that changed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Delta {
+    pub apple: TermPoint,
+    pub players: Vec<PlayerDelta>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlayerDelta {
+    pub player: usize,
+    /// The new head, if the snake moved
+    pub head: Option<BodySegment>,
+    /// The snake's length afterwards, which drops the tail unless it grew
+    pub length: usize,
+    pub score: usize,
+    pub apples_eaten: usize,
+    pub death: Option<Death>,
+}
+
+impl Snapshot {
+    /// What changed between this snapshot and `next`, a tick later
+    pub fn delta(&self, next: &Snapshot) -> Delta {
+        let players = self
+            .players
+            .iter()
+            .zip(next.players.iter())
+            .enumerate()
+            .filter(|(_, (prev, next))| prev != next)
+            .map(|(player, (prev, next))| PlayerDelta {
+                player,
+                head: (next.body.first() != prev.body.first()).then(|| next.body[0]),
+                length: next.body.len(),
+                score: next.score,
+                apples_eaten: next.apples_eaten,
+                death: next.death,
+            })
+            .collect();
+
+        Delta {
+            apple: next.apple,
+            players,
+        }
+    }
+
+    /// Brings the snapshot forward by a tick
+    pub fn apply(&mut self, delta: &Delta) {
+        self.apple = delta.apple;
+        for change in delta.players.iter() {
+            let Some(state) = self.players.get_mut(change.player) else {
+                continue;
+            };
+            if let Some(head) = change.head {
+                state.body.insert(0, head);
+            }
+            state.body.truncate(change.length);
+            state.score = change.score;
+            state.apples_eaten = change.apples_eaten;
+            state.death = change.death;
+        }
+    }
+}
+
+/// One of the snakes on the board and how it's doing
+pub struct Player {
+    pub snake: Snake,
+    pub score: usize,
+    pub apples_eaten: usize,
+    /// What killed the snake, once it's dead
+    pub death: Option<Death>,
+}
+
+impl Player {
+    pub fn is_alive(&self) -> bool {
+        self.death.is_none()
+    }
+}
+
+/// The game rules, independent of any terminal or input source
+///
+/// All randomness comes from an RNG seeded at construction, so the same seed
+/// and sequence of inputs always plays out the same game
+pub struct Simulation {
+    config: SimConfig,
+    board: Board,
+    players: Vec<Player>,
+    // ordered so that picking the nth open cell is stable across runs
+    open_space: BTreeSet<TermPoint>,
+    obstacles: BTreeSet<TermPoint>,
+    apple: TermPoint,
+    rng: StdRng,
+}
+
+/// Where a player's snake starts, as the position of its head and the way
+/// it's heading.
+///
+/// Maps have a spawn point for the first player, and the second starts
+/// opposite it. Otherwise the snakes are spread out from the top row to the
+/// bottom one, alternately starting on the left heading right and on the
+/// right heading left, so none can be longer than its row. On a map, a spawn
+/// that isn't in `open_space` or faces out of it is moved to the nearest one
+/// that's clear, so no snake dies on its first step.
+fn spawn_point(
+    config: &SimConfig,
+    player: usize,
+    open_space: &BTreeSet<TermPoint>,
+) -> (TermPoint, Dir) {
+    let board = config.board;
+    let clear = |(pos, dir): (TermPoint, Dir)| {
+        open_space.contains(&pos) && open_space.contains(&board.step(pos, dir))
+    };
+    let length = config.starting_length.clamp(1, board.width - 2);
+    let last_row = board.height - 2;
+    let row = match config.players {
+        0 | 1 => 1,
+        players => 1 + player * (last_row - 1) / (players - 1),
+    };
+    let spawn = match player % 2 {
+        0 => (TermPoint::new(row, length), Dir::Right),
+        _ => (TermPoint::new(row, board.width - 1 - length), Dir::Left),
+    };
+    let Some(map) = &config.map else {
+        return spawn;
+    };
+
+    let mirrored = TermPoint::new(
+        board.height - 1 - map.spawn.row,
+        board.width - 1 - map.spawn.col,
+    );
+    match player {
+        0 => return (map.spawn, map.spawn_dir),
+        1 if clear((mirrored, map.spawn_dir.opposite())) => {
+            return (mirrored, map.spawn_dir.opposite());
+        }
+        _ if clear(spawn) => return spawn,
+        _ => {}
+    }
+    let (pos, dir) = spawn;
+    open_space
+        .iter()
+        .map(|&open| (open, dir))
+        .filter(|&spawn| clear(spawn))
+        .min_by_key(|(open, _)| (open.row.abs_diff(pos.row), open.col.abs_diff(pos.col)))
+        .unwrap_or(spawn)
+}
+
+impl Simulation {
+    pub fn new(config: SimConfig) -> Self {
+        let board = config.board;
+        let obstacles = match &config.map {
+            Some(map) => map.walls.clone(),
+            None => BTreeSet::new(),
+        };
+
+        let mut open_space: BTreeSet<TermPoint> = BTreeSet::new();
+        for col in 1..board.width - 1 {
+            for row in 1..board.height - 1 {
+                open_space.insert(TermPoint::new(row, col));
+            }
+        }
+        for wall in obstacles.iter() {
+            open_space.remove(wall);
+        }
+
+        // lay each body out behind its head, stopping early if it runs into
+        // something, and always leaving room for an apple
+        let mut players = Vec::new();
+        for player in 0..config.players.max(1) {
+            let (spawn, spawn_dir) = spawn_point(&config, player, &open_space);
+            let mut snake = Snake::new();
+            snake.body.push_back(BodySegment {
+                pos: spawn,
+                dir: spawn_dir,
+            });
+            open_space.remove(&spawn);
+            let mut pos = spawn;
+            while snake.body.len() < config.starting_length && open_space.len() > 1 {
+                pos = board.step(pos, spawn_dir.opposite());
+                if !open_space.remove(&pos) {
+                    break;
+                }
+                snake.body.push_back(BodySegment {
+                    pos,
+                    dir: spawn_dir,
+                });
+            }
+            players.push(Player {
+                snake,
+                score: 0,
+                apples_eaten: 0,
+                death: None,
+            });
+        }
+
+        let rng = StdRng::seed_from_u64(config.seed);
+        let mut sim = Simulation {
+            config,
+            board,
+            players,
+            open_space,
+            obstacles,
+            apple: TermPoint::new(0, 0),
+            rng,
+        };
+        sim.add_apple();
+
+        sim
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            players: self
+                .players
+                .iter()
+                .map(|player| PlayerState {
+                    body: player.snake.body.iter().copied().collect(),
+                    score: player.score,
+                    apples_eaten: player.apples_eaten,
+                    death: player.death,
+                })
+                .collect(),
+            apple: self.apple,
+        }
+    }
+
+    pub fn config(&self) -> &SimConfig {
+        &self.config
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.config.seed
+    }
+
+    pub fn board(&self) -> Board {
+        self.board
+    }
+
+    /// Every snake on the board, in player order
+    pub fn players(&self) -> &[Player] {
+        &self.players
+    }
+
+    /// The first player's snake, the only one in a single player game
+    pub fn snake(&self) -> &Snake {
+        &self.players[0].snake
+    }
+
+    /// The first player's score
+    pub fn score(&self) -> usize {
+        self.players[0].score
+    }
+
+    /// The current speed level, which multiplies the points for each apple
+    pub fn level(&self) -> usize {
+        let apples = self.players.iter().map(|p| p.apples_eaten).sum();
+        self.config.level(apples)
+    }
+
+    pub fn apple(&self) -> TermPoint {
+        self.apple
+    }
+
+    /// How many apples the first player has eaten
+    pub fn apples_eaten(&self) -> usize {
+        self.players[0].apples_eaten
+    }
+
+    /// What ended the first player's game, once it's over
+    pub fn death(&self) -> Option<Death> {
+        self.players[0].death
+    }
+
+    /// Places a new apple, on one of the map's apple spots if it has any free
+    fn add_apple(&mut self) {
+        let spots: Vec<TermPoint> = match &self.config.map {
+            Some(map) => map
+                .apple_spots
+                .iter()
+                .filter(|spot| self.open_space.contains(spot))
+                .copied()
+                .collect(),
+            None => Vec::new(),
+        };
+        if spots.is_empty() {
+            let idx = self.rng.gen_range(0..self.open_space.len());
+            self.apple = *self.open_space.iter().nth(idx).unwrap();
+        } else {
+            self.apple = spots[self.rng.gen_range(0..spots.len())];
+        }
+    }
+
+    /// Picks the direction for a snake's next move, ignoring inputs that don't
+    /// steer and attempts to reverse straight into the body
+    fn next_dir(&self, player: usize, input: UserInput) -> Dir {
+        let curr_dir = self.players[player].snake.head().dir;
+        match input.dir() {
+            Some(dir) if !curr_dir.is_opposite(dir) => dir,
+            _ => curr_dir,
+        }
+    }
+
+    /// What, if anything, the snake that just moved its head to `head` ran into
+    fn collision(&self, player: usize, head: TermPoint) -> Option<DeathCause> {
+        if self.board.is_border(head) {
+            return Some(DeathCause::Wall);
+        }
+        if self.obstacles.contains(&head) {
+            return Some(DeathCause::Obstacle);
+        }
+        if let Some(segment) = self.players[player]
+            .snake
+            .body
+            .iter()
+            .skip(1)
+            .position(|seg| seg.pos == head)
+        {
+            return Some(DeathCause::SelfCollision {
+                segment: segment + 1,
+            });
+        }
+        // dead snakes stay where they fell, so they're still in the way
+        self.players
+            .iter()
+            .enumerate()
+            .filter(|(other, _)| *other != player)
+            .find_map(|(_, other)| other.snake.body.iter().position(|seg| seg.pos == head))
+            .map(|segment| DeathCause::Opponent { segment })
+    }
+
+    /// Whether enough snakes have died to end the game, and how it ended
+    fn deaths_outcome(&self) -> Option<GameState> {
+        if self.players.len() == 1 {
+            return self.players[0].death.map(GameState::Over);
+        }
+        let mut alive = (0..self.players.len()).filter(|&p| self.players[p].is_alive());
+        match (alive.next(), alive.next()) {
+            (winner, None) => Some(GameState::RoundOver { winner }),
+            _ => None,
+        }
+    }
+
+    /// Kills a player's snake where it is, e.g. when they stop responding
+    pub fn forfeit(&mut self, player: usize, cause: DeathCause) -> GameState {
+        let player = &mut self.players[player];
+        if player.is_alive() {
+            player.death = Some(Death {
+                cause,
+                head: player.snake.head().pos,
+            });
+        }
+
+        self.deaths_outcome().unwrap_or(GameState::Continue)
+    }
+
+    /// Advances a single player game by one tick
+    pub fn update_state(&mut self, input: UserInput) -> GameState {
+        self.update(&[input])
+    }
+
+    /// Advances the game by one tick, steering each player's snake with the
+    /// input at the same index. Snakes without an input keep going straight.
+    ///
+    /// Every snake moves at once, so two heads meeting in the same cell is a
+    /// head on crash for both.
+    pub fn update(&mut self, inputs: &[UserInput]) -> GameState {
+        let moving: Vec<usize> = (0..self.players.len())
+            .filter(|&player| self.players[player].is_alive())
+            .collect();
+        let mut old_tails = Vec::with_capacity(moving.len());
+        for &player in moving.iter() {
+            let input = inputs.get(player).copied().unwrap_or(UserInput::Unknown);
+            let dir = self.next_dir(player, input);
+            let snake = &mut self.players[player].snake;
+            old_tails.push(*snake.body.back().unwrap());
+            snake.move_body(dir, &self.board);
+        }
+
+        // snakes grow before collisions are checked, so nothing can slip into
+        // the cell a growing tail stays in
+        let mut eaters = Vec::new();
+        for (&player, old_tail) in moving.iter().zip(old_tails) {
+            let snake = &mut self.players[player].snake;
+            if snake.head().pos == self.apple {
+                snake.extend_body(old_tail);
+                eaters.push(player);
+            } else {
+                self.open_space.insert(old_tail.pos);
+            }
+        }
+        for &player in moving.iter() {
+            self.open_space
+                .remove(&self.players[player].snake.head().pos);
+        }
+
+        let deaths: Vec<(usize, Death)> = moving
+            .iter()
+            .filter_map(|&player| {
+                let head = self.players[player].snake.head().pos;
+                let cause = self.collision(player, head)?;
+                Some((player, Death { cause, head }))
+            })
+            .collect();
+        for &(player, death) in deaths.iter() {
+            self.players[player].death = Some(death);
+        }
+
+        let apple_eaten = !eaters.is_empty();
+        let points = POINTS_PER_APPLE * self.level();
+        let mut last_eater = None;
+        for player in eaters {
+            let player_state = &mut self.players[player];
+            if player_state.is_alive() {
+                player_state.score += points;
+                player_state.apples_eaten += 1;
+                last_eater = Some(player);
+            }
+        }
+
+        if let Some(state) = self.deaths_outcome() {
+            return state;
+        }
+
+        if apple_eaten {
+            // the snakes now cover every cell they can reach
+            if self.open_space.is_empty() {
+                return match self.players.len() {
+                    1 => GameState::Win,
+                    _ => GameState::RoundOver { winner: last_eater },
+                };
+            }
+            self.add_apple();
+        }
+        GameState::Continue
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tiny_sim(width: usize, height: usize, starting_length: usize) -> Simulation {
+        Simulation::new(SimConfig {
+            starting_length,
+            seed: 7,
+            ..SimConfig::new(Board::new(width + 2, height + 2, WallMode::Solid), 1)
+        })
+    }
+
+    fn versus_sim(width: usize, height: usize, starting_length: usize) -> Simulation {
+        Simulation::new(SimConfig {
+            players: 2,
+            ..tiny_sim(width, height, starting_length).config
+        })
+    }
+
+    /// Steers around the edge of a playfield two cells tall, which passes
+    /// through every cell of it
+    fn loop_input(sim: &Simulation) -> UserInput {
+        let head = sim.snake().head().pos;
+        let last_col = sim.board().width - 2;
+        match (head.row, head.col) {
+            (1, col) if col == last_col => UserInput::Down,
+            (1, _) => UserInput::Right,
+            (_, 1) => UserInput::Up,
+            _ => UserInput::Left,
+        }
+    }
+
+    /// Plays until the game ends, returning the final state and how many ticks it took
+    fn play_out(sim: &mut Simulation, max_ticks: usize) -> (GameState, usize) {
+        for tick in 1..=max_ticks {
+            let state = sim.update_state(loop_input(sim));
+            if state != GameState::Continue {
+                return (state, tick);
+            }
+        }
+        panic!("Game didn't finish in {max_ticks} ticks");
+    }
+
+    #[test]
+    fn filling_a_2x2_board_wins() {
+        let mut sim = tiny_sim(2, 2, 1);
+        let (state, _) = play_out(&mut sim, 100);
+
+        assert_eq!(state, GameState::Win);
+        assert_eq!(sim.snake().body.len(), 4);
+        assert_eq!(sim.apples_eaten(), 3);
+        assert_eq!(sim.score(), 3 * POINTS_PER_APPLE);
+        assert_eq!(sim.death(), None);
+    }
+
+    #[test]
+    fn filling_a_wider_board_wins() {
+        let mut sim = tiny_sim(4, 2, 2);
+        let (state, _) = play_out(&mut sim, 1000);
+
+        assert_eq!(state, GameState::Win);
+        assert_eq!(sim.snake().body.len(), 8);
+        assert_eq!(sim.apples_eaten(), 6);
+    }
+
+    #[test]
+    fn win_is_reported_on_the_tick_the_board_fills() {
+        let mut sim = tiny_sim(2, 2, 1);
+        loop {
+            let cells_taken = sim.snake().body.len();
+            let state = sim.update_state(loop_input(&sim));
+            match state {
+                GameState::Continue => assert!(sim.snake().body.len() < 4),
+                GameState::Win => {
+                    assert_eq!(cells_taken, 3);
+                    assert_eq!(sim.snake().body.len(), 4);
+                    break;
+                }
+                GameState::Over(death) => panic!("{death} while following the loop"),
+                GameState::RoundOver { .. } => panic!("Round over with only one player"),
+            }
+        }
+    }
+
+    #[test]
+    fn board_with_no_room_left_starts_with_an_apple() {
+        // a snake as long as the whole board would leave nowhere for an apple
+        let sim = tiny_sim(2, 2, 4);
+
+        assert!(sim.snake().body.len() < 4);
+        assert!(sim.snake().body.iter().all(|seg| seg.pos != sim.apple()));
+        assert!(!sim.board().is_border(sim.apple()));
+    }
+
+    #[test]
+    fn running_into_the_body_reports_the_segment_hit() {
+        let mut sim = tiny_sim(3, 3, 1);
+        // a hook shape, with the head just below the body heading left
+        sim.players[0].snake.body = [(2, 2), (2, 3), (1, 3), (1, 2), (1, 1)]
+            .into_iter()
+            .map(|(row, col)| BodySegment::new(row, col, Dir::Left))
+            .collect();
+        let state = sim.update_state(UserInput::Up);
+
+        let death = Death {
+            cause: DeathCause::SelfCollision { segment: 4 },
+            head: TermPoint::new(1, 2),
+        };
+        assert_eq!(state, GameState::Over(death));
+    }
+
+    #[test]
+    fn running_into_the_wall_is_not_a_win() {
+        let mut sim = tiny_sim(2, 2, 1);
+        let state = sim.update_state(UserInput::Up);
+
+        let death = Death {
+            cause: DeathCause::Wall,
+            head: TermPoint::new(0, 1),
+        };
+        assert_eq!(state, GameState::Over(death));
+        assert_eq!(sim.death(), Some(death));
+    }
+
+    #[test]
+    fn eating_an_apple_grows_the_snake_and_scores() {
+        let mut sim = tiny_sim(6, 3, 2);
+        let head = sim.snake().head().pos;
+        sim.apple = TermPoint::new(head.row, head.col + 1);
+        let state = sim.update_state(UserInput::Unknown);
+
+        assert_eq!(state, GameState::Continue);
+        assert_eq!(
+            sim.snake().head().pos,
+            TermPoint::new(head.row, head.col + 1)
+        );
+        assert_eq!(sim.snake().body.len(), 3);
+        assert_eq!(sim.score(), POINTS_PER_APPLE);
+        assert_eq!(sim.apples_eaten(), 1);
+        assert!(sim.snake().body.iter().all(|seg| seg.pos != sim.apple()));
+    }
+
+    #[test]
+    fn apples_are_worth_more_at_each_level() {
+        let apples = APPLES_PER_LEVEL * MAX_LEVEL + 1;
+        let mut sim = Simulation::new(SimConfig {
+            progressive: true,
+            ..tiny_sim(apples + 2, 1, 1).config
+        });
+        for eaten in 0..apples {
+            let level = (1 + eaten / APPLES_PER_LEVEL).min(MAX_LEVEL);
+            assert_eq!(sim.level(), level);
+            let head = sim.snake().head().pos;
+            sim.apple = TermPoint::new(head.row, head.col + 1);
+            let score = sim.score();
+            assert_eq!(sim.update_state(UserInput::Unknown), GameState::Continue);
+
+            assert_eq!(sim.score() - score, POINTS_PER_APPLE * level);
+        }
+        assert_eq!(sim.level(), MAX_LEVEL);
+    }
+
+    #[test]
+    fn moving_without_eating_keeps_the_length_and_score() {
+        let mut sim = tiny_sim(6, 3, 2);
+        sim.apple = TermPoint::new(3, 6);
+        sim.update_state(UserInput::Down);
+
+        assert_eq!(sim.snake().head().pos, TermPoint::new(2, 2));
+        assert_eq!(sim.snake().body.len(), 2);
+        assert_eq!(sim.score(), 0);
+    }
+
+    #[test]
+    fn reversing_into_the_body_keeps_going_straight() {
+        let mut sim = tiny_sim(6, 3, 3);
+        let head = *sim.snake().head();
+        let state = sim.update_state(UserInput::Left);
+
+        assert_eq!(state, GameState::Continue);
+        assert_eq!(sim.snake().head().dir, head.dir);
+        assert_eq!(sim.snake().head().pos, TermPoint::new(1, 4));
+    }
+
+    #[test]
+    fn wrapping_walls_come_out_the_other_side() {
+        let mut sim = Simulation::new(SimConfig {
+            board: Board::new(5, 4, WallMode::Wrap),
+            ..tiny_sim(3, 2, 1).config
+        });
+        sim.apple = TermPoint::new(2, 3);
+        let state = sim.update_state(UserInput::Up);
+
+        assert_eq!(state, GameState::Continue);
+        assert_eq!(sim.snake().head().pos, TermPoint::new(2, 1));
+    }
+
+    #[test]
+    fn same_seed_and_inputs_play_out_the_same() {
+        let mut first = tiny_sim(4, 2, 1);
+        let mut second = tiny_sim(4, 2, 1);
+        loop {
+            let state = first.update_state(loop_input(&first));
+            assert_eq!(second.update_state(loop_input(&second)), state);
+            assert_eq!(first.apple(), second.apple());
+            assert_eq!(first.score(), second.score());
+            assert_eq!(first.snake().head().pos, second.snake().head().pos);
+            if state != GameState::Continue {
+                break;
+            }
+        }
+    }
+
+    #[test]
+    fn different_seeds_place_different_apples() {
+        let apples = |seed| {
+            let mut sim = Simulation::new(SimConfig {
+                seed,
+                ..tiny_sim(20, 10, 3).config
+            });
+            let mut apples = vec![sim.apple()];
+            for _ in 0..10 {
+                sim.add_apple();
+                apples.push(sim.apple());
+            }
+            apples
+        };
+
+        assert_eq!(apples(1), apples(1));
+        assert_ne!(apples(1), apples(2));
+    }
+
+    #[test]
+    fn second_snake_starts_on_the_bottom_row_heading_left() {
+        let sim = versus_sim(7, 3, 2);
+        let heads: Vec<(TermPoint, Dir)> = sim
+            .players()
+            .iter()
+            .map(|player| (player.snake.head().pos, player.snake.head().dir))
+            .collect();
+
+        assert_eq!(
+            heads,
+            [
+                (TermPoint::new(1, 2), Dir::Right),
+                (TermPoint::new(3, 6), Dir::Left),
+            ]
+        );
+    }
+
+    #[test]
+    fn meeting_head_on_is_a_draw() {
+        // both snakes start on the only row, facing each other
+        let mut sim = versus_sim(5, 1, 1);
+        let mut state = GameState::Continue;
+        for _ in 0..2 {
+            state = sim.update(&[UserInput::Unknown, UserInput::Unknown]);
+        }
+
+        assert_eq!(state, GameState::RoundOver { winner: None });
+        for player in sim.players() {
+            let death = player.death.unwrap();
+            assert_eq!(death.cause, DeathCause::Opponent { segment: 0 });
+            assert_eq!(death.head, TermPoint::new(1, 3));
+        }
+    }
+
+    #[test]
+    fn running_into_the_other_snake_loses_the_round() {
+        let mut sim = versus_sim(5, 5, 1);
+        let body = |cells: [(usize, usize); 3], dir| {
+            cells
+                .into_iter()
+                .map(|(row, col)| BodySegment::new(row, col, dir))
+                .collect()
+        };
+        // the first snake heads left across the second one's path up the side
+        sim.players[0].snake.body = body([(3, 2), (3, 3), (3, 4)], Dir::Left);
+        sim.players[1].snake.body = body([(2, 1), (3, 1), (4, 1)], Dir::Up);
+        let state = sim.update(&[UserInput::Left, UserInput::Up]);
+
+        assert_eq!(state, GameState::RoundOver { winner: Some(1) });
+        let death = Death {
+            cause: DeathCause::Opponent { segment: 2 },
+            head: TermPoint::new(3, 1),
+        };
+        assert_eq!(sim.players()[0].death, Some(death));
+        assert!(sim.players()[1].is_alive());
+    }
+
+    #[test]
+    fn snakes_dont_spawn_facing_a_wall_on_a_map() {
+        // the walls are in front of both the mirrored spawn, bottom right,
+        // and the usual one for the second player
+        let rows = [">.......", "........", "....#.#."];
+        let map = Map::from_rows("Blocked".to_string(), rows.map(String::from).to_vec()).unwrap();
+        let mut sim = Simulation::new(SimConfig {
+            map: Some(Arc::new(map)),
+            ..SimConfig::new(Board::new(10, 5, WallMode::Solid), 2)
+        });
+
+        for player in sim.players() {
+            let head = player.snake.head();
+            let ahead = sim.board().step(head.pos, head.dir);
+            assert!(!sim.obstacles.contains(&head.pos));
+            assert!(!sim.obstacles.contains(&ahead) && !sim.board().is_border(ahead));
+        }
+        assert_eq!(sim.players()[1].snake.head().pos, TermPoint::new(3, 4));
+        assert_eq!(sim.update(&[UserInput::Unknown; 2]), GameState::Continue);
+    }
+
+    #[test]
+    fn applying_each_delta_keeps_a_snapshot_in_step() {
+        let mut sim = versus_sim(8, 6, 3);
+        let mut copy = sim.snapshot();
+        let inputs = [UserInput::Down, UserInput::Up];
+        for _ in 0..6 {
+            let before = sim.snapshot();
+            let state = sim.update(&inputs);
+            copy.apply(&before.delta(&sim.snapshot()));
+
+            assert_eq!(copy, sim.snapshot());
+            if state != GameState::Continue {
+                break;
+            }
+        }
+    }
+}
@@ -0,0 +1,104 @@
+use std::{io::Write, panic, process};
+
+use anyhow::Context;
+use console::Term;
+
+/// Exit code for a process ended by SIGINT, as shells report it
+const INTERRUPTED_EXIT_CODE: i32 = 130;
+
+const ENTER_ALT_SCREEN: &[u8] = b"\x1b[?1049h";
+const LEAVE_ALT_SCREEN: &[u8] = b"\x1b[?1049l";
+
+/// Takes over the terminal for the game, putting it back the way it was found
+/// when dropped, when the program panics, or when it's interrupted with Ctrl-C
+/// or killed with SIGTERM.
+///
+/// The game is drawn on the alternate screen, so the shell's scrollback is
+/// left untouched.
+pub struct TerminalGuard {
+    state: SavedState,
+}
+
+impl TerminalGuard {
+    /// Switches to the alternate screen and hides the cursor, installing the
+    /// panic and signal handlers that undo it. Only one guard can exist at a
+    /// time.
+    pub fn new(term: Term) -> anyhow::Result<Self> {
+        let state = SavedState::save(term);
+
+        let hook_state = state.clone();
+        let prev_hook = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            hook_state.restore();
+            prev_hook(info);
+        }));
+        let signal_state = state.clone();
+        ctrlc::set_handler(move || {
+            signal_state.restore();
+            process::exit(INTERRUPTED_EXIT_CODE);
+        })
+        .context("Failed to install the Ctrl-C handler")?;
+
+        let mut term = &state.term;
+        term.write_all(ENTER_ALT_SCREEN)?;
+        term.clear_screen()?;
+        term.hide_cursor()?;
+
+        Ok(TerminalGuard { state })
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        self.state.restore();
+    }
+}
+
+/// What's needed to put the terminal back, cheap to copy into handlers
+#[derive(Clone)]
+struct SavedState {
+    term: Term,
+    #[cfg(unix)]
+    termios: Option<libc::termios>,
+}
+
+impl SavedState {
+    fn save(term: Term) -> Self {
+        #[cfg(unix)]
+        let termios = {
+            use std::os::fd::AsRawFd;
+
+            let mut termios = std::mem::MaybeUninit::uninit();
+            // SAFETY: `tcgetattr` only writes to the struct it's given, and we
+            // only read it back if it succeeded
+            unsafe {
+                (libc::tcgetattr(term.as_raw_fd(), termios.as_mut_ptr()) == 0)
+                    .then(|| termios.assume_init())
+            }
+        };
+
+        SavedState {
+            term,
+            #[cfg(unix)]
+            termios,
+        }
+    }
+
+    /// Best effort, since this may run while already handling a failure
+    fn restore(&self) {
+        #[cfg(unix)]
+        if let Some(termios) = &self.termios {
+            use std::os::fd::AsRawFd;
+
+            // SAFETY: `termios` was filled in by `tcgetattr` on the same terminal
+            unsafe {
+                libc::tcsetattr(self.term.as_raw_fd(), libc::TCSANOW, termios);
+            }
+        }
+        let mut term = &self.term;
+        let _ = term.write_all(b"\x1b[0m");
+        let _ = term.write_all(LEAVE_ALT_SCREEN);
+        let _ = term.show_cursor();
+        let _ = term.flush();
+    }
+}
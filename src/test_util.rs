@@ -0,0 +1,29 @@
+//! Helpers shared by the unit tests
+
+use std::{fs, path::PathBuf};
+
+/// A fresh directory for a test's files, removed again on drop, even if the
+/// test fails
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    /// Creates the directory, named after `name` so that tests running at
+    /// the same time don't share one
+    pub fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("rusty_snake_{name}_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        TempDir(dir)
+    }
+
+    /// The path of `file` in the directory
+    pub fn join(&self, file: &str) -> PathBuf {
+        self.0.join(file)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}